@@ -1,6 +1,32 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub const PROTOCOL_VERSION: u32 = 2;
+
+pub const PROTOCOL_VERSION_HEADER: &str = "x-net-syringe-protocol";
+pub const PROTOCOL_FEATURES_HEADER: &str = "x-net-syringe-features";
+
+/// Features implemented by this client.
+pub const SUPPORTED_FEATURES: &[Feature] = &[Feature::ExceptionTables];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    TlsCallbacks,
+    ExceptionTables,
+    Pe32,
+}
+
+impl Feature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::TlsCallbacks => "tls-callbacks",
+            Feature::ExceptionTables => "exception-tables",
+            Feature::Pe32 => "pe32",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Import {
@@ -26,16 +52,45 @@ pub struct Region {
     pub characteristics: u32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DiscardableSection {
+    pub name: String,
+    pub rva: u32,
+    pub size: u32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetLibraryResponse {
     pub size_of_image: u32,
-    pub entry_point: u32,
+    #[serde(default)]
+    pub entry_point: Option<u32>,
+    #[serde(default)]
+    pub image_base: u64,
+    #[serde(default)]
+    pub relocs_stripped: bool,
     pub imports: HashMap<String, Vec<Import>>,
     pub regions: Vec<Region>,
+    #[serde(default)]
+    pub discardable: Vec<DiscardableSection>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetLibraryExportResponse {
+    pub rva: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct GuardCfFunctions {
+    pub check_function: u64,
+    pub dispatch_function: u64,
 }
 
 #[derive(Debug, Serialize)]
 pub struct GetLibraryMapping {
     pub allocation_base: u64,
     pub imports: HashMap<String, Vec<Import>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guard_cf: Option<GuardCfFunctions>,
 }
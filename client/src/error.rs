@@ -4,19 +4,44 @@ use crate::process;
 pub enum Error {
     #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
+    #[cfg(windows)]
     #[error(transparent)]
     WindowsError(#[from] windows::core::Error),
     #[error(transparent)]
     ProcessError(#[from] process::Error),
+    #[error(transparent)]
+    CommonError(#[from] net_syringe_common::Error),
 
     #[error("import not found")]
     ImportNotFound,
+    #[error("export {0} not found")]
+    ExportNotFound(String),
+    #[error("entry point returned FALSE for DLL_PROCESS_ATTACH")]
+    EntryPointFailed,
+    #[error("failed to register the exception table")]
+    ExceptionTableFailed,
+    #[error("service does not report library hashes")]
+    MissingLibraryHash,
+    #[error("unknown execution method {0}")]
+    UnknownExecutionMethod(String),
+    #[error("invalid execution option: {0}")]
+    InvalidExecutionOption(String),
     #[error("window not found")]
     WindowNotFound,
     #[error("client error")]
     HttpClientError,
+    #[error("invalid image envelope")]
+    InvalidEnvelope,
+    #[error("incompatible service: {0}")]
+    IncompatibleService(String),
+    /// The call was not started in time and will not be made.
     #[error("timeout")]
     Timeout,
+    /// The call started but did not return in time, so it may still be running in the target.
+    #[error("timed out while the call was still running")]
+    StillRunning,
+    #[error("library at 0x{0:X} was left in place, a call into it timed out while running")]
+    LibraryLeftRunning(u64),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
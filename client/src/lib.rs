@@ -0,0 +1,46 @@
+//! Client for the net-syringe service: maps libraries the service prepares into a target process.
+//!
+//! ```no_run
+//! # #[cfg(windows)]
+//! # fn main() -> net_syringe::Result<()> {
+//! use net_syringe::{ExecutionByWindowsHook, ProcessTrait, Syringe, Win32Process};
+//!
+//! let mut process = Win32Process::find_process_by_name("Notepad.exe")?;
+//! process.attach()?;
+//!
+//! let execution_method = ExecutionByWindowsHook {
+//!     window_class: Some("Notepad".to_string()),
+//!     ..Default::default()
+//! };
+//!
+//! let report = Syringe::new(&process, "http://localhost:3000")
+//!     .base_independent(true)
+//!     .inject("test.dll", &execution_method, None)?;
+//!
+//! println!("injected at 0x{:X}", report.module.module_base);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(windows))]
+//! # fn main() {}
+//! ```
+//!
+//! [`ProcessTrait`] abstracts the target process, [`ExecutionMethod`] how calls are made in it and
+//! [`ImportResolver`] the lookup of imported functions. The Win32 implementations of these are only
+//! available on Windows; the protocol, mapping and planning logic builds everywhere, and [`MockProcess`] stands
+//! in for a target on other platforms.
+
+pub mod dto;
+pub mod error;
+pub mod process;
+pub mod syringe;
+
+pub use error::{Error, Result};
+#[cfg(windows)]
+pub use process::Win32Process;
+pub use process::{MockProcess, ProcessTrait};
+pub use syringe::{
+    DiscardSections, EntryResult, ExecutionMethod, ExecutionMethods, ExecutionOptions,
+    ImportResolver, InitCall, InjectedModule, InjectionReport, RemoteCall, Syringe,
+};
+#[cfg(windows)]
+pub use syringe::{ExecutionByWindowsHook, LocalImportResolver};
@@ -1,16 +1,10 @@
-use crate::process::{ProcessTrait, Win32Process};
 use clap::Parser;
-use log::error;
+use net_syringe::DiscardSections;
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
-
-mod dto;
-mod error;
-mod process;
-mod syringe;
-
-use syringe::*;
+use std::path::PathBuf;
 
 #[derive(Parser)]
+#[cfg_attr(not(windows), allow(dead_code))]
 #[command(version, about, long_about = None)]
 struct Args {
     #[arg(short, long, help = "Name of the process to target")]
@@ -19,11 +13,94 @@ struct Args {
     #[arg(short, long, help = "ID of the library to inject")]
     library: String,
 
-    #[arg(long, default_value = "http://localhost:3000", help = "Server host address")]
+    #[arg(
+        long,
+        default_value = "http://localhost:3000",
+        help = "Server host address"
+    )]
     host: String,
 
+    #[arg(
+        long,
+        default_value = "windows-hook",
+        help = "Name of the method used to call functions in the target"
+    )]
+    execution_method: String,
+
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        value_parser = parse_execution_option,
+        help = "Option for the execution method, such as timeout=5000 for windows-hook"
+    )]
+    execution_option: Vec<(String, String)>,
+
     #[arg(long, help = "Target window class for execution by windows hook")]
-    window_class: String,
+    window_class: Option<String>,
+
+    #[arg(long, help = "Target window name for execution by windows hook")]
+    window_name: Option<String>,
+
+    #[arg(
+        long,
+        help = "Fetch the image in one request and apply relocations and imports locally"
+    )]
+    base_independent: bool,
+
+    #[arg(
+        long,
+        help = "Exported function to call after DllMain with a pointer to the init payload"
+    )]
+    init_export: Option<String>,
+
+    #[arg(
+        long,
+        requires = "init_export",
+        help = "File passed to the init export, as a pointer to its contents"
+    )]
+    init_payload: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Leave the target's allocations in place when injection fails, for debugging"
+    )]
+    keep_on_failure: bool,
+
+    #[arg(
+        long,
+        default_value = "relocations",
+        help = "Discardable sections to decommit after initialization: none, relocations or all"
+    )]
+    discard_sections: DiscardSections,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Eject the library again after the given number of seconds"
+    )]
+    eject_after: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with = "eject_after",
+        help = "Keep polling the service and reinject the library whenever it changes"
+    )]
+    hot_reload: bool,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        default_value_t = 1000,
+        help = "Interval between polls for --hot-reload"
+    )]
+    poll_interval: u64,
+}
+
+fn parse_execution_option(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {}", value))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -42,6 +119,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ColorChoice::Auto,
     )?;
 
+    run(args)
+}
+
+#[cfg(not(windows))]
+fn run(_args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    Err("injection is only supported on Windows".into())
+}
+
+#[cfg(windows)]
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    use log::{error, info};
+    use net_syringe::{
+        ExecutionMethods, ExecutionOptions, InitCall, ProcessTrait, Syringe, Win32Process,
+    };
+    use std::{fs, thread::sleep, time::Duration};
+
     let mut process = match Win32Process::find_process_by_name(&args.process) {
         Ok(process) => process,
         Err(err) => {
@@ -55,15 +148,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Err(err.into());
     }
 
-    let syringe = Syringe::new(&process, args.host);
+    let init = match args.init_export {
+        Some(export) => Some(InitCall {
+            export,
+            payload: match &args.init_payload {
+                Some(path) => fs::read(path)?,
+                None => Vec::new(),
+            },
+        }),
+        None => None,
+    };
+
+    let syringe = Syringe::new(&process, args.host)
+        .base_independent(args.base_independent)
+        .keep_on_failure(args.keep_on_failure)
+        .discard_sections(args.discard_sections);
+
+    let mut options: ExecutionOptions = args.execution_option.into_iter().collect();
+    if let Some(window_class) = args.window_class {
+        options.insert("window_class".to_string(), window_class);
+    }
+    if let Some(window_name) = args.window_name {
+        options.insert("window_name".to_string(), window_name);
+    }
+
+    let methods = ExecutionMethods::default();
+    let execution_method = match methods.create(&args.execution_method, &options) {
+        Ok(method) => method,
+        Err(err) => {
+            error!(
+                "available execution methods: {}",
+                methods.names().collect::<Vec<_>>().join(", ")
+            );
+            return Err(err.into());
+        }
+    };
 
-    let execution_method = ExecutionByWindowsHook {
-        window_class: Some(args.window_class.to_string()),
-        ..Default::default()
+    if args.hot_reload {
+        syringe.hot_reload(
+            &args.library,
+            execution_method.as_ref(),
+            init.as_ref(),
+            Duration::from_millis(args.poll_interval),
+        )?;
+
+        return Ok(());
+    }
+
+    let mut report = syringe.inject(&args.library, execution_method.as_ref(), init.as_ref())?;
+
+    info!("injected library at 0x{:X}", report.module.module_base);
+
+    if let Some(result) = report.entry_point_result {
+        info!("entry point returned {}", result);
     }
-    .into();
 
-    syringe.inject(&args.library, &execution_method)?;
+    if let Some(result) = report.init_result {
+        info!("init export returned {}", result);
+    }
+
+    if let Some(seconds) = args.eject_after {
+        sleep(Duration::from_secs(seconds));
+
+        syringe.eject(&mut report.module, execution_method.as_ref())?;
+
+        info!("ejected library");
+    }
 
     Ok(())
 }
@@ -1,5 +1,6 @@
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[cfg(windows)]
     #[error(transparent)]
     WindowsError(#[from] windows::core::Error),
     #[error(transparent)]
@@ -12,6 +13,12 @@ pub enum Error {
     ProcessNotFound,
     #[error("failed allocation")]
     FailedAllocation,
+    #[error("invalid page protection 0x{0:X}")]
+    InvalidProtection(u32),
+    #[error("access violation at 0x{0:X}")]
+    AccessViolation(u64),
+    #[error("cannot free memory at 0x{0:X}")]
+    InvalidFree(u64),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,562 @@
+//! A process that only exists in memory, for exercising the mapping logic without a Windows
+//! target.
+//!
+//! [`MockProcess`] models a virtual address space the way `VirtualAllocEx` and friends manage it:
+//! reservations on the 64 KiB allocation granularity, per-page commit state and protection, and
+//! the error cases of the Win32 implementation. It is stricter than `WriteProcessMemory`, which
+//! temporarily makes read-only pages writable, so writes after a protection change are caught.
+//!
+//! Paired with a service running in-process (`service::router_with_libraries` served on a local
+//! port) a [`Syringe`](crate::Syringe) can fetch, map and protect a library end to end; only calls
+//! into the target need an execution method that does not run code in this process.
+
+use crate::process::{
+    Error, MEM_COMMIT, MEM_DECOMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+    PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE, ProcessTrait, Result,
+};
+use std::{cell::RefCell, collections::BTreeMap, ops::Range};
+
+const PAGE_SIZE: u64 = 0x1000;
+const ALLOCATION_GRANULARITY: u64 = 0x1_0000;
+
+/// Where allocations without a requested address start, above the usual image bases.
+const FIRST_ALLOCATION: u64 = 0x2_0000_0000;
+
+fn align_down(value: u64, alignment: u64) -> u64 {
+    value & !(alignment - 1)
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.saturating_add(alignment - 1) & !(alignment - 1)
+}
+
+fn is_valid_protection(protection: u32) -> bool {
+    matches!(
+        protection,
+        PAGE_NOACCESS
+            | PAGE_READONLY
+            | PAGE_READWRITE
+            | PAGE_EXECUTE
+            | PAGE_EXECUTE_READ
+            | PAGE_EXECUTE_READWRITE
+    )
+}
+
+fn is_readable(protection: u32) -> bool {
+    matches!(
+        protection,
+        PAGE_READONLY | PAGE_READWRITE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE
+    )
+}
+
+fn is_writable(protection: u32) -> bool {
+    matches!(protection, PAGE_READWRITE | PAGE_EXECUTE_READWRITE)
+}
+
+/// A successful `mem_write`, in the order they happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+/// A reserved region, `None` marks pages that are not committed.
+struct Allocation {
+    size: u64,
+    pages: Vec<Option<u32>>,
+    data: Vec<u8>,
+}
+
+impl Allocation {
+    fn page_range(&self, offset: u64, size: u64) -> Range<usize> {
+        (offset / PAGE_SIZE) as usize..(align_up(offset + size, PAGE_SIZE) / PAGE_SIZE) as usize
+    }
+}
+
+#[derive(Default)]
+struct AddressSpace {
+    allocations: BTreeMap<u64, Allocation>,
+    writes: Vec<MemoryWrite>,
+}
+
+impl AddressSpace {
+    fn overlaps(&self, start: u64, end: u64) -> Option<u64> {
+        self.allocations
+            .range(..end)
+            .next_back()
+            .filter(|(base, allocation)| **base + allocation.size > start)
+            .map(|(base, allocation)| base + allocation.size)
+    }
+
+    fn free_address(&self, size: u64) -> u64 {
+        let mut candidate = FIRST_ALLOCATION;
+
+        while let Some(end) = self.overlaps(candidate, candidate + size) {
+            candidate = align_up(end, ALLOCATION_GRANULARITY);
+        }
+
+        candidate
+    }
+
+    /// Returns the allocation fully containing `address..address + size` and the offset of
+    /// `address` within it.
+    fn find(&mut self, address: u64, size: u64) -> Option<(&mut Allocation, u64)> {
+        let (base, allocation) = self.allocations.range_mut(..=address).next_back()?;
+        let offset = address - base;
+
+        if offset.checked_add(size)? > allocation.size {
+            return None;
+        }
+
+        Some((allocation, offset))
+    }
+
+    /// Returns the committed pages covering `address..address + size` if all of them satisfy
+    /// `check`, which is not called for empty ranges.
+    fn access(
+        &mut self,
+        address: u64,
+        size: u64,
+        check: fn(u32) -> bool,
+    ) -> Result<Option<(&mut Allocation, u64)>> {
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let (allocation, offset) = self
+            .find(address, size)
+            .ok_or(Error::AccessViolation(address))?;
+
+        let pages = allocation.page_range(offset, size);
+        if !allocation.pages[pages]
+            .iter()
+            .all(|page| page.is_some_and(check))
+        {
+            return Err(Error::AccessViolation(address));
+        }
+
+        Ok(Some((allocation, offset)))
+    }
+}
+
+/// An in-memory [`ProcessTrait`] implementation, see the [module documentation](self).
+pub struct MockProcess {
+    process_id: u32,
+    attached: bool,
+    memory: RefCell<AddressSpace>,
+}
+
+impl MockProcess {
+    /// Creates a detached process with an empty address space.
+    pub fn new(process_id: u32) -> Self {
+        Self {
+            process_id,
+            attached: false,
+            memory: RefCell::default(),
+        }
+    }
+
+    /// Returns the base and size of every reserved region, in address order.
+    pub fn allocations(&self) -> Vec<(u64, usize)> {
+        self.memory
+            .borrow()
+            .allocations
+            .iter()
+            .map(|(base, allocation)| (*base, allocation.size as usize))
+            .collect()
+    }
+
+    /// Returns the protection of the page containing `address`, or `None` if it is not committed.
+    pub fn protection(&self, address: u64) -> Option<u32> {
+        let mut memory = self.memory.borrow_mut();
+        let (allocation, offset) = memory.find(address, 1)?;
+
+        allocation.pages[(offset / PAGE_SIZE) as usize]
+    }
+
+    /// Reads committed memory regardless of its protection, for inspecting what was mapped.
+    pub fn peek(&self, address: u64, size: usize) -> Option<Vec<u8>> {
+        if size == 0 {
+            return Some(Vec::new());
+        }
+
+        let mut memory = self.memory.borrow_mut();
+        let (allocation, offset) = memory.access(address, size as u64, |_| true).ok()??;
+
+        Some(allocation.data[offset as usize..offset as usize + size].to_vec())
+    }
+
+    /// Returns the successful writes so far.
+    pub fn writes(&self) -> Vec<MemoryWrite> {
+        self.memory.borrow().writes.clone()
+    }
+
+    fn ensure_attached(&self) -> Result<()> {
+        if !self.attached {
+            return Err(Error::NotAttached);
+        }
+
+        Ok(())
+    }
+}
+
+impl ProcessTrait for MockProcess {
+    fn attach(&mut self) -> Result<()> {
+        if self.attached {
+            return Err(Error::AlreadyAttached);
+        }
+
+        self.attached = true;
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        self.ensure_attached()?;
+
+        self.attached = false;
+        Ok(())
+    }
+
+    fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    fn mem_read(&self, address: u64, buffer: &mut [u8]) -> Result<()> {
+        self.ensure_attached()?;
+
+        let mut memory = self.memory.borrow_mut();
+        if let Some((allocation, offset)) =
+            memory.access(address, buffer.len() as u64, is_readable)?
+        {
+            let offset = offset as usize;
+            buffer.copy_from_slice(&allocation.data[offset..offset + buffer.len()]);
+        }
+
+        Ok(())
+    }
+
+    fn mem_write(&self, address: u64, buffer: &[u8]) -> Result<()> {
+        self.ensure_attached()?;
+
+        let mut memory = self.memory.borrow_mut();
+        if let Some((allocation, offset)) =
+            memory.access(address, buffer.len() as u64, is_writable)?
+        {
+            let offset = offset as usize;
+            allocation.data[offset..offset + buffer.len()].copy_from_slice(buffer);
+        }
+
+        memory.writes.push(MemoryWrite {
+            address,
+            data: buffer.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn mem_alloc(
+        &self,
+        address: Option<u64>,
+        size: usize,
+        allocation_type: u32,
+        protection: u32,
+    ) -> Result<u64> {
+        self.ensure_attached()?;
+
+        if !is_valid_protection(protection) {
+            return Err(Error::InvalidProtection(protection));
+        }
+
+        if size == 0 || allocation_type & (MEM_RESERVE | MEM_COMMIT) == 0 {
+            return Err(Error::FailedAllocation);
+        }
+
+        let commit = allocation_type & MEM_COMMIT != 0;
+        let mut memory = self.memory.borrow_mut();
+
+        match address {
+            // Committing within an existing reservation.
+            Some(address) if allocation_type & MEM_RESERVE == 0 => {
+                let start = align_down(address, PAGE_SIZE);
+                let end = align_up(address + size as u64, PAGE_SIZE);
+
+                let (allocation, offset) = memory
+                    .find(start, end - start)
+                    .ok_or(Error::FailedAllocation)?;
+
+                let pages = allocation.page_range(offset, end - start);
+                allocation.pages[pages].fill(Some(protection));
+
+                Ok(start)
+            }
+            _ => {
+                let (base, size) = match address {
+                    Some(address) => {
+                        let base = align_down(address, ALLOCATION_GRANULARITY);
+                        (base, align_up(address + size as u64, PAGE_SIZE) - base)
+                    }
+                    None => {
+                        let size = align_up(size as u64, PAGE_SIZE);
+                        (memory.free_address(size), size)
+                    }
+                };
+
+                if memory.overlaps(base, base + size).is_some() {
+                    return Err(Error::FailedAllocation);
+                }
+
+                memory.allocations.insert(
+                    base,
+                    Allocation {
+                        size,
+                        pages: vec![commit.then_some(protection); (size / PAGE_SIZE) as usize],
+                        data: vec![0; size as usize],
+                    },
+                );
+
+                Ok(base)
+            }
+        }
+    }
+
+    fn mem_free(&self, address: u64, size: Option<usize>, allocation_type: u32) -> Result<()> {
+        self.ensure_attached()?;
+
+        let mut memory = self.memory.borrow_mut();
+
+        match allocation_type {
+            MEM_RELEASE if size.unwrap_or(0) == 0 => memory
+                .allocations
+                .remove(&address)
+                .map(|_| ())
+                .ok_or(Error::InvalidFree(address)),
+            MEM_DECOMMIT => {
+                let size = match size.unwrap_or(0) {
+                    // Like `VirtualFreeEx`, a zero size decommits the whole region from its base.
+                    0 => {
+                        memory
+                            .allocations
+                            .get(&address)
+                            .ok_or(Error::InvalidFree(address))?
+                            .size
+                    }
+                    size => size as u64,
+                };
+
+                let start = align_down(address, PAGE_SIZE);
+                let end = align_up(address + size, PAGE_SIZE);
+
+                let (allocation, offset) = memory
+                    .find(start, end - start)
+                    .ok_or(Error::InvalidFree(address))?;
+
+                let pages = allocation.page_range(offset, end - start);
+                allocation.pages[pages].fill(None);
+                allocation.data[offset as usize..(offset + end - start) as usize].fill(0);
+
+                Ok(())
+            }
+            _ => Err(Error::InvalidFree(address)),
+        }
+    }
+
+    fn mem_protect(&self, address: u64, size: usize, protect: u32) -> Result<u32> {
+        self.ensure_attached()?;
+
+        if !is_valid_protection(protect) {
+            return Err(Error::InvalidProtection(protect));
+        }
+
+        let start = align_down(address, PAGE_SIZE);
+        let end = align_up(address + size.max(1) as u64, PAGE_SIZE);
+
+        let mut memory = self.memory.borrow_mut();
+        let (allocation, offset) = memory
+            .access(start, end - start, |_| true)?
+            .ok_or(Error::AccessViolation(address))?;
+
+        let pages = allocation.page_range(offset, end - start);
+        let previous = allocation.pages[pages.start].unwrap_or(PAGE_NOACCESS);
+        allocation.pages[pages].fill(Some(protect));
+
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process() -> MockProcess {
+        let mut process = MockProcess::new(1);
+        process.attach().unwrap();
+        process
+    }
+
+    fn alloc(process: &MockProcess, size: usize, protection: u32) -> u64 {
+        process
+            .mem_alloc(None, size, MEM_RESERVE | MEM_COMMIT, protection)
+            .unwrap()
+    }
+
+    #[test]
+    fn refuses_everything_while_detached() {
+        let mut process = process();
+        let address = alloc(&process, 0x1000, PAGE_READWRITE);
+        process.detach().unwrap();
+
+        assert!(matches!(process.detach(), Err(Error::NotAttached)));
+        assert!(matches!(
+            process.mem_alloc(None, 0x1000, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE),
+            Err(Error::NotAttached)
+        ));
+        assert!(matches!(
+            process.mem_read(address, &mut [0; 4]),
+            Err(Error::NotAttached)
+        ));
+        assert!(matches!(
+            process.mem_write(address, &[1; 4]),
+            Err(Error::NotAttached)
+        ));
+        assert!(matches!(
+            process.mem_protect(address, 0x1000, PAGE_READONLY),
+            Err(Error::NotAttached)
+        ));
+        assert!(matches!(
+            process.mem_free(address, None, MEM_RELEASE),
+            Err(Error::NotAttached)
+        ));
+
+        process.attach().unwrap();
+        assert!(matches!(process.attach(), Err(Error::AlreadyAttached)));
+    }
+
+    #[test]
+    fn allocates_on_the_allocation_granularity() {
+        let process = process();
+
+        let first = alloc(&process, 0x1800, PAGE_READWRITE);
+        let second = alloc(&process, 0x1000, PAGE_READWRITE);
+
+        assert_eq!(first, FIRST_ALLOCATION);
+        assert_eq!(second, FIRST_ALLOCATION + ALLOCATION_GRANULARITY);
+        assert_eq!(process.allocations(), [(first, 0x2000), (second, 0x1000)]);
+
+        // Overlapping reservations fail.
+        assert!(matches!(
+            process.mem_alloc(Some(first + 0x1000), 0x1000, MEM_RESERVE, PAGE_READWRITE),
+            Err(Error::FailedAllocation)
+        ));
+    }
+
+    #[test]
+    fn refuses_invalid_protections() {
+        let process = process();
+        let address = alloc(&process, 0x1000, PAGE_READWRITE);
+
+        // PAGE_GUARD is not modeled.
+        assert!(matches!(
+            process.mem_alloc(None, 0x1000, MEM_RESERVE | MEM_COMMIT, 0x104),
+            Err(Error::InvalidProtection(0x104))
+        ));
+        assert!(matches!(
+            process.mem_protect(address, 0x1000, 0),
+            Err(Error::InvalidProtection(0))
+        ));
+        assert_eq!(process.protection(address), Some(PAGE_READWRITE));
+    }
+
+    #[test]
+    fn refuses_writes_to_read_only_pages() {
+        let process = process();
+        let address = alloc(&process, 0x2000, PAGE_READWRITE);
+
+        process.mem_write(address + 0xFFC, &[1; 4]).unwrap();
+        assert_eq!(
+            process
+                .mem_protect(address + 0x1000, 0x1000, PAGE_EXECUTE_READ)
+                .unwrap(),
+            PAGE_READWRITE
+        );
+
+        // Crossing into the protected page fails as a whole.
+        assert!(matches!(
+            process.mem_write(address + 0xFFC, &[2; 8]),
+            Err(Error::AccessViolation(_))
+        ));
+        assert_eq!(
+            process.peek(address + 0xFFC, 8).unwrap(),
+            [1, 1, 1, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            process.writes(),
+            [MemoryWrite {
+                address: address + 0xFFC,
+                data: vec![1; 4],
+            }]
+        );
+
+        let mut buffer = [0; 8];
+        process.mem_read(address + 0xFFC, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn refuses_access_to_decommitted_and_noaccess_pages() {
+        let process = process();
+        let address = alloc(&process, 0x3000, PAGE_READWRITE);
+
+        process
+            .mem_free(address + 0x1000, Some(0x1000), MEM_DECOMMIT)
+            .unwrap();
+        process
+            .mem_protect(address + 0x2000, 0x1000, PAGE_NOACCESS)
+            .unwrap();
+
+        assert_eq!(process.protection(address + 0x1000), None);
+        assert!(matches!(
+            process.mem_read(address + 0x1000, &mut [0; 4]),
+            Err(Error::AccessViolation(_))
+        ));
+        assert!(matches!(
+            process.mem_read(address + 0x2000, &mut [0; 4]),
+            Err(Error::AccessViolation(_))
+        ));
+        assert!(matches!(
+            process.mem_read(address + 0x3000, &mut [0; 4]),
+            Err(Error::AccessViolation(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_double_frees() {
+        let process = process();
+        let address = alloc(&process, 0x2000, PAGE_READWRITE);
+
+        // Releasing needs the base and no size.
+        assert!(matches!(
+            process.mem_free(address + 0x1000, None, MEM_RELEASE),
+            Err(Error::InvalidFree(_))
+        ));
+        assert!(matches!(
+            process.mem_free(address, Some(0x1000), MEM_RELEASE),
+            Err(Error::InvalidFree(_))
+        ));
+
+        process.mem_free(address, None, MEM_RELEASE).unwrap();
+        assert!(process.allocations().is_empty());
+
+        assert!(matches!(
+            process.mem_free(address, None, MEM_RELEASE),
+            Err(Error::InvalidFree(free)) if free == address
+        ));
+        assert!(matches!(
+            process.mem_free(address, None, MEM_DECOMMIT),
+            Err(Error::InvalidFree(_))
+        ));
+    }
+}
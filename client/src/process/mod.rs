@@ -1,12 +1,28 @@
 #![allow(dead_code)]
 
 pub use error::*;
+pub use mock::{MemoryWrite, MockProcess};
+pub use net_syringe_common::protection::{
+    PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_READONLY,
+    PAGE_READWRITE,
+};
+#[cfg(windows)]
 pub use win32::Win32Process;
 
 pub mod error;
+pub mod mock;
+#[cfg(windows)]
 pub mod win32;
 
+// Allocation types taken by `mem_alloc` and `mem_free`, with their Windows values.
+pub const MEM_COMMIT: u32 = 0x1000;
+pub const MEM_RESERVE: u32 = 0x2000;
+pub const MEM_DECOMMIT: u32 = 0x4000;
+pub const MEM_RELEASE: u32 = 0x8000;
+
+/// Memory and lifecycle operations on a target process.
 pub trait ProcessTrait {
+    /// Opens the process, memory operations fail with [`Error::NotAttached`] before.
     fn attach(&mut self) -> Result<()>;
     fn detach(&mut self) -> Result<()>;
     fn is_attached(&self) -> bool;
@@ -14,6 +30,7 @@ pub trait ProcessTrait {
 
     fn mem_read(&self, address: u64, buffer: &mut [u8]) -> Result<()>;
     fn mem_write(&self, address: u64, buffer: &[u8]) -> Result<()>;
+    /// Allocates memory like `VirtualAllocEx`, at `address` if given, and returns its address.
     fn mem_alloc(
         &self,
         address: Option<u64>,
@@ -21,6 +38,8 @@ pub trait ProcessTrait {
         allocation_type: u32,
         protection: u32,
     ) -> Result<u64>;
+    /// Releases or decommits memory like `VirtualFreeEx`, `size` is required to decommit.
     fn mem_free(&self, address: u64, size: Option<usize>, allocation_type: u32) -> Result<()>;
+    /// Changes the protection like `VirtualProtectEx` and returns the previous protection.
     fn mem_protect(&self, address: u64, size: usize, protect: u32) -> Result<u32>;
 }
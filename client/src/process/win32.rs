@@ -21,6 +21,7 @@ use windows::Win32::{
     },
 };
 
+/// A process accessed through the Win32 API.
 pub struct Win32Process {
     process_id: u32,
     process_handle: HANDLE,
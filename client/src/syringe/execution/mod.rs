@@ -0,0 +1,142 @@
+use crate::{
+    error::{Error, Result},
+    process::{MEM_RELEASE, ProcessTrait},
+};
+use std::collections::BTreeMap;
+
+#[cfg(windows)]
+pub use windows_hook::ExecutionByWindowsHook;
+
+// Only used by the Windows hook, but kept portable so the stub can be built and inspected anywhere.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod stub;
+#[cfg(windows)]
+mod windows_hook;
+
+pub const DLL_PROCESS_DETACH: u32 = 0;
+pub const DLL_PROCESS_ATTACH: u32 = 1;
+
+/// A call to `function(first, second, third)` in the target process, made with the calling
+/// convention of `DllMain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteCall {
+    pub function: u64,
+    pub first: u64,
+    pub second: u32,
+    pub third: u64,
+}
+
+impl RemoteCall {
+    /// `DllMain(module_base, reason, NULL)`.
+    pub fn dll_main(module_base: u64, entry_point: u64, reason: u32) -> Self {
+        Self {
+            function: entry_point,
+            first: module_base,
+            second: reason,
+            third: 0,
+        }
+    }
+}
+
+pub(super) struct ScopedRemoteAllocation<'a>(pub u64, pub &'a dyn ProcessTrait);
+
+impl Drop for ScopedRemoteAllocation<'_> {
+    fn drop(&mut self) {
+        self.1.mem_free(self.0, None, MEM_RELEASE).ok();
+    }
+}
+
+/// The value returned by a function called in the target process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryResult(pub i32);
+
+impl EntryResult {
+    /// Whether a `BOOL` result, such as `DllMain`'s, is TRUE.
+    pub fn succeeded(self) -> bool {
+        self.0 != 0
+    }
+
+    /// Whether a `BOOLEAN` result, which only sets the low byte of the register, is TRUE.
+    pub fn succeeded_boolean(self) -> bool {
+        self.0 as u8 != 0
+    }
+}
+
+/// How the client makes calls in the target process.
+pub trait ExecutionMethod {
+    /// Makes `call` in the target process and returns the function's return value once it
+    /// returned.
+    fn call(&self, process: &dyn ProcessTrait, call: RemoteCall) -> Result<EntryResult>;
+
+    /// Calls the entry point of the library at `module_base` with `reason`.
+    fn execute(
+        &self,
+        process: &dyn ProcessTrait,
+        module_base: u64,
+        entry_point: u64,
+        reason: u32,
+    ) -> Result<EntryResult> {
+        self.call(
+            process,
+            RemoteCall::dll_main(module_base, entry_point, reason),
+        )
+    }
+}
+
+/// Options passed to an execution method created by name, such as `window_class`.
+pub type ExecutionOptions = BTreeMap<String, String>;
+
+type ExecutionMethodFactory = Box<dyn Fn(&ExecutionOptions) -> Result<Box<dyn ExecutionMethod>>>;
+
+/// Execution methods by name, for selecting one at runtime. The default registry contains the
+/// built-in methods of the platform.
+pub struct ExecutionMethods {
+    factories: BTreeMap<String, ExecutionMethodFactory>,
+}
+
+impl ExecutionMethods {
+    pub fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a method, replacing any method registered under the same name.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&ExecutionOptions) -> Result<Box<dyn ExecutionMethod>> + 'static,
+    {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        options: &ExecutionOptions,
+    ) -> Result<Box<dyn ExecutionMethod>> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::UnknownExecutionMethod(name.to_string()))?;
+
+        factory(options)
+    }
+}
+
+impl Default for ExecutionMethods {
+    #[cfg_attr(not(windows), allow(unused_mut))]
+    fn default() -> Self {
+        let mut methods = Self::empty();
+
+        #[cfg(windows)]
+        methods.register("windows-hook", |options| {
+            Ok(Box::new(ExecutionByWindowsHook::from_options(options)?))
+        });
+
+        methods
+    }
+}
@@ -0,0 +1,371 @@
+//! The `WH_GETMESSAGE` hook procedure that makes a [`RemoteCall`](super::RemoteCall) from the
+//! thread of a window, assembled from typed instructions.
+//!
+//! The procedure claims the call by swapping [`ShellCodeData::status`] from idle to running, so
+//! only the first message hooked makes it, then stores the return value and marks it done:
+//!
+//! ```text
+//!     push rbx
+//!     sub rsp, 0x20                       ; shadow space, keeps rsp 16-byte aligned
+//!     mov rbx, <data>
+//!     mov ecx, STATUS_RUNNING
+//!     xor eax, eax                        ; STATUS_IDLE
+//!     lock cmpxchg [rbx + status], ecx
+//!     jne done
+//!     mov rcx, [rbx + first]
+//!     mov edx, [rbx + second]
+//!     mov r8, [rbx + third]
+//!     call [rbx + function]
+//!     mov [rbx + result], eax
+//!     mov dword [rbx + status], STATUS_DONE
+//! done:
+//!     xor eax, eax
+//!     add rsp, 0x20
+//!     pop rbx
+//!     ret
+//! ```
+//!
+//! The field displacements come from `offset_of!` on the `#[repr(C)]` struct, so they cannot
+//! drift from the layout the client reads back. The tests decode the output with iced-x86 and
+//! compare it with this listing, formatted the way `objdump -D -b binary -m i386:x86-64 -M intel`
+//! prints it.
+
+use std::mem::offset_of;
+
+pub const STATUS_IDLE: i32 = 0;
+pub const STATUS_RUNNING: i32 = 1;
+pub const STATUS_DONE: i32 = 2;
+
+/// Shared between the client and the hook procedure, which receives its address.
+#[repr(C)]
+pub struct ShellCodeData {
+    pub status: i32,
+    pub result: i32,
+
+    pub function: u64,
+    pub first: u64,
+    pub second: u32,
+    pub third: u64,
+}
+
+// Fields are addressed with 8-bit displacements.
+const _: () = assert!(size_of::<ShellCodeData>() <= i8::MAX as usize);
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    R8 = 8,
+}
+
+impl Register {
+    fn low_bits(self) -> u8 {
+        self as u8 & 0b111
+    }
+
+    fn is_extended(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+/// The few x64 instructions the hook procedure needs.
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    /// Emits a REX prefix if any of its bits are set, or `wide` selects 64-bit operands.
+    fn rex(&mut self, wide: bool, reg: Register, base: Register) {
+        let rex =
+            0x40 | (wide as u8) << 3 | (reg.is_extended() as u8) << 2 | base.is_extended() as u8;
+
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    /// Emits a ModR/M byte for `[base + disp8]`. `rsp` and `r12` would need a SIB byte.
+    fn memory_operand(&mut self, reg: u8, base: Register, displacement: usize) {
+        assert!(base.low_bits() != 0b100, "base register needs a SIB byte");
+
+        self.code
+            .push(0b01 << 6 | (reg & 0b111) << 3 | base.low_bits());
+        self.code.push(i8::try_from(displacement).unwrap() as u8);
+    }
+
+    fn push(&mut self, reg: Register) {
+        self.rex(false, Register::Rax, reg);
+        self.code.push(0x50 + reg.low_bits());
+    }
+
+    fn pop(&mut self, reg: Register) {
+        self.rex(false, Register::Rax, reg);
+        self.code.push(0x58 + reg.low_bits());
+    }
+
+    fn sub_rsp(&mut self, value: u8) {
+        self.code.extend([0x48, 0x83, 0xec, value]);
+    }
+
+    fn add_rsp(&mut self, value: u8) {
+        self.code.extend([0x48, 0x83, 0xc4, value]);
+    }
+
+    /// `mov reg, imm64`
+    fn mov_imm64(&mut self, reg: Register, value: u64) {
+        self.rex(true, Register::Rax, reg);
+        self.code.push(0xb8 + reg.low_bits());
+        self.code.extend(value.to_le_bytes());
+    }
+
+    /// `mov reg32, imm32`
+    fn mov_imm32(&mut self, reg: Register, value: u32) {
+        self.rex(false, Register::Rax, reg);
+        self.code.push(0xb8 + reg.low_bits());
+        self.code.extend(value.to_le_bytes());
+    }
+
+    /// `xor reg32, reg32`
+    fn zero(&mut self, reg: Register) {
+        self.rex(false, reg, reg);
+        self.code.push(0x31);
+        self.code
+            .push(0b11 << 6 | reg.low_bits() << 3 | reg.low_bits());
+    }
+
+    /// `lock cmpxchg dword [base + displacement], src`
+    fn lock_cmpxchg32(&mut self, base: Register, displacement: usize, src: Register) {
+        self.code.push(0xf0);
+        self.rex(false, src, base);
+        self.code.extend([0x0f, 0xb1]);
+        self.memory_operand(src.low_bits(), base, displacement);
+    }
+
+    /// `mov dst, [base + displacement]`, with a 64-bit or 32-bit destination.
+    fn load(&mut self, wide: bool, dst: Register, base: Register, displacement: usize) {
+        self.rex(wide, dst, base);
+        self.code.push(0x8b);
+        self.memory_operand(dst.low_bits(), base, displacement);
+    }
+
+    /// `mov dword [base + displacement], src`
+    fn store32(&mut self, base: Register, displacement: usize, src: Register) {
+        self.rex(false, src, base);
+        self.code.push(0x89);
+        self.memory_operand(src.low_bits(), base, displacement);
+    }
+
+    /// `mov dword [base + displacement], imm32`
+    fn store_imm32(&mut self, base: Register, displacement: usize, value: u32) {
+        self.rex(false, Register::Rax, base);
+        self.code.push(0xc7);
+        self.memory_operand(0, base, displacement);
+        self.code.extend(value.to_le_bytes());
+    }
+
+    /// `call qword [base + displacement]`
+    fn call_indirect(&mut self, base: Register, displacement: usize) {
+        self.rex(false, Register::Rax, base);
+        self.code.push(0xff);
+        self.memory_operand(2, base, displacement);
+    }
+
+    /// `jne rel8` to a label bound later, returns the position to patch.
+    fn jne_forward(&mut self) -> usize {
+        self.code.extend([0x75, 0x00]);
+        self.code.len()
+    }
+
+    /// Points a forward jump at the current position.
+    fn bind(&mut self, jump: usize) {
+        self.code[jump - 1] = u8::try_from(self.code.len() - jump).unwrap();
+    }
+
+    fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+}
+
+/// Assembles the hook procedure for a [`ShellCodeData`] at `data_address` in the target.
+pub fn hook_procedure(data_address: u64) -> Vec<u8> {
+    use Register::*;
+
+    let mut asm = Assembler::default();
+
+    asm.push(Rbx);
+    asm.sub_rsp(0x20);
+    asm.mov_imm64(Rbx, data_address);
+
+    asm.mov_imm32(Rcx, STATUS_RUNNING as u32);
+    asm.zero(Rax);
+    asm.lock_cmpxchg32(Rbx, offset_of!(ShellCodeData, status), Rcx);
+    let claimed = asm.jne_forward();
+
+    asm.load(true, Rcx, Rbx, offset_of!(ShellCodeData, first));
+    asm.load(false, Rdx, Rbx, offset_of!(ShellCodeData, second));
+    asm.load(true, R8, Rbx, offset_of!(ShellCodeData, third));
+    asm.call_indirect(Rbx, offset_of!(ShellCodeData, function));
+    asm.store32(Rbx, offset_of!(ShellCodeData, result), Rax);
+    asm.store_imm32(Rbx, offset_of!(ShellCodeData, status), STATUS_DONE as u32);
+
+    asm.bind(claimed);
+    asm.zero(Rax);
+    asm.add_rsp(0x20);
+    asm.pop(Rbx);
+    asm.ret();
+
+    asm.code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter, OpKind};
+
+    const DATA: u64 = 0x1122_3344_5566_7788;
+
+    /// The listing in the module documentation as `objdump -M intel` decodes the procedure for
+    /// `DATA`, except that objdump calls the `mov` with a 64-bit immediate `movabs`.
+    const LISTING: &[(u64, &[u8], &str)] = &[
+        (0x00, &[0x53], "push rbx"),
+        (0x01, &[0x48, 0x83, 0xec, 0x20], "sub rsp,0x20"),
+        (
+            0x05,
+            &[0x48, 0xbb, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+            "mov rbx,0x1122334455667788",
+        ),
+        (0x0f, &[0xb9, 0x01, 0x00, 0x00, 0x00], "mov ecx,0x1"),
+        (0x14, &[0x31, 0xc0], "xor eax,eax"),
+        (
+            0x16,
+            &[0xf0, 0x0f, 0xb1, 0x4b, 0x00],
+            "lock cmpxchg DWORD PTR [rbx+0x0],ecx",
+        ),
+        (0x1b, &[0x75, 0x18], "jne 0x35"),
+        (
+            0x1d,
+            &[0x48, 0x8b, 0x4b, 0x10],
+            "mov rcx,QWORD PTR [rbx+0x10]",
+        ),
+        (0x21, &[0x8b, 0x53, 0x18], "mov edx,DWORD PTR [rbx+0x18]"),
+        (
+            0x24,
+            &[0x4c, 0x8b, 0x43, 0x20],
+            "mov r8,QWORD PTR [rbx+0x20]",
+        ),
+        (0x28, &[0xff, 0x53, 0x08], "call QWORD PTR [rbx+0x8]"),
+        (0x2b, &[0x89, 0x43, 0x04], "mov DWORD PTR [rbx+0x4],eax"),
+        (
+            0x2e,
+            &[0xc7, 0x43, 0x00, 0x02, 0x00, 0x00, 0x00],
+            "mov DWORD PTR [rbx+0x0],0x2",
+        ),
+        (0x35, &[0x31, 0xc0], "xor eax,eax"),
+        (0x37, &[0x48, 0x83, 0xc4, 0x20], "add rsp,0x20"),
+        (0x3b, &[0x5b], "pop rbx"),
+        (0x3c, &[0xc3], "ret"),
+    ];
+
+    fn decode(code: &[u8]) -> Vec<Instruction> {
+        Decoder::with_ip(64, code, 0, DecoderOptions::NONE)
+            .into_iter()
+            .collect()
+    }
+
+    /// Formats like objdump's Intel syntax.
+    fn formatter() -> IntelFormatter {
+        let mut formatter = IntelFormatter::new();
+        let options = formatter.options_mut();
+        options.set_hex_prefix("0x");
+        options.set_hex_suffix("");
+        options.set_small_hex_numbers_in_decimal(false);
+        options.set_space_after_operand_separator(false);
+        options.set_uppercase_keywords(true);
+        options.set_memory_size_options(iced_x86::MemorySizeOptions::Always);
+        options.set_show_zero_displacements(true);
+        options.set_show_branch_size(false);
+        options.set_branch_leading_zeros(false);
+        formatter
+    }
+
+    #[test]
+    fn matches_the_listing() {
+        let code = hook_procedure(DATA);
+        let instructions = decode(&code);
+        let mut formatter = formatter();
+
+        assert_eq!(instructions.len(), LISTING.len());
+
+        for (instruction, (offset, bytes, text)) in instructions.iter().zip(LISTING) {
+            let mut output = String::new();
+            formatter.format(instruction, &mut output);
+
+            assert_eq!(output, *text);
+            assert_eq!(instruction.ip(), *offset, "{text}");
+            assert_eq!(
+                &code[instruction.ip() as usize..instruction.next_ip() as usize],
+                *bytes,
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn addresses_the_shared_data() {
+        let code = hook_procedure(DATA);
+        assert_eq!(code[0x07..0x0f], DATA.to_le_bytes());
+
+        let displacements = decode(&code)
+            .iter()
+            .filter(|instruction| instruction.memory_base() == iced_x86::Register::RBX)
+            .map(|instruction| instruction.memory_displacement64() as usize)
+            .collect::<Vec<_>>();
+
+        // Fields in the order the listing uses them.
+        assert_eq!(
+            displacements,
+            [
+                offset_of!(ShellCodeData, status),
+                offset_of!(ShellCodeData, first),
+                offset_of!(ShellCodeData, second),
+                offset_of!(ShellCodeData, third),
+                offset_of!(ShellCodeData, function),
+                offset_of!(ShellCodeData, result),
+                offset_of!(ShellCodeData, status),
+            ]
+        );
+        assert_eq!(displacements, [0x0, 0x10, 0x18, 0x20, 0x8, 0x4, 0x0]);
+    }
+
+    #[test]
+    fn skips_calls_already_claimed() {
+        let instructions = decode(&hook_procedure(DATA));
+
+        let jump = instructions
+            .iter()
+            .find(|instruction| instruction.op0_kind() == OpKind::NearBranch64)
+            .unwrap()
+            .near_branch_target();
+        let store_done = instructions
+            .iter()
+            .rposition(|instruction| instruction.memory_base() == iced_x86::Register::RBX)
+            .unwrap();
+
+        // Past marking the call done, to the epilogue.
+        assert_eq!(jump, instructions[store_done + 1].ip());
+        assert_eq!(jump, 0x35);
+    }
+
+    #[test]
+    fn stores_the_statuses() {
+        let code = hook_procedure(DATA);
+
+        // `mov ecx, STATUS_RUNNING` and the immediate of `mov dword [rbx + status], STATUS_DONE`.
+        assert_eq!(code[0x10..0x14], (STATUS_RUNNING as u32).to_le_bytes());
+        assert_eq!(code[0x31..0x35], (STATUS_DONE as u32).to_le_bytes());
+        assert_eq!(STATUS_IDLE, 0, "xor eax, eax compares against idle");
+    }
+}
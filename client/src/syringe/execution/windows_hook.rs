@@ -1,8 +1,12 @@
 use crate::{
     error::{Error, Result},
-    process::ProcessTrait,
+    process::{MEM_COMMIT, MEM_RESERVE, PAGE_EXECUTE_READ, PAGE_READWRITE, ProcessTrait},
+    syringe::execution::{
+        EntryResult, ExecutionMethod, ExecutionOptions, RemoteCall, ScopedRemoteAllocation,
+        stub::{STATUS_DONE, STATUS_IDLE, ShellCodeData, hook_procedure},
+    },
 };
-use log::{error, info};
+use log::{error, info, warn};
 use std::{
     ffi::CString,
     thread::sleep,
@@ -11,11 +15,7 @@ use std::{
 use windows::{
     Win32::{
         Foundation::{HINSTANCE, LPARAM, WPARAM},
-        System::{
-            LibraryLoader::{GetProcAddress, LoadLibraryA},
-            Memory::{MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE_READ, PAGE_READWRITE},
-            SystemServices::DLL_PROCESS_ATTACH,
-        },
+        System::LibraryLoader::LoadLibraryA,
         UI::WindowsAndMessaging::{
             FindWindowA, GetWindowThreadProcessId, HHOOK, PostThreadMessageA, SetWindowsHookExA,
             UnhookWindowsHookEx, WH_GETMESSAGE, WM_NULL,
@@ -24,10 +24,13 @@ use windows::{
     core::PCSTR,
 };
 
+/// Calls functions from the thread of a window found by name or class, through a
+/// `WH_GETMESSAGE` hook.
 #[derive(Debug)]
 pub struct ExecutionByWindowsHook {
     pub window_name: Option<String>,
     pub window_class: Option<String>,
+    /// How long to wait for each call to finish.
     pub timeout: Duration,
 }
 
@@ -41,28 +44,38 @@ impl Default for ExecutionByWindowsHook {
     }
 }
 
-#[derive(Debug)]
-pub enum ExecutionMethod {
-    ByWindowsHook(ExecutionByWindowsHook),
-}
+impl ExecutionByWindowsHook {
+    /// Reads `window_name`, `window_class` and `timeout` (in milliseconds) from the options. One
+    /// of the window options is required.
+    pub fn from_options(options: &ExecutionOptions) -> Result<Self> {
+        let mut method = Self {
+            window_name: options.get("window_name").cloned(),
+            window_class: options.get("window_class").cloned(),
+            ..Default::default()
+        };
+
+        if method.window_name.is_none() && method.window_class.is_none() {
+            return Err(Error::InvalidExecutionOption(
+                "window_name or window_class is required".to_string(),
+            ));
+        }
 
-impl Into<ExecutionMethod> for ExecutionByWindowsHook {
-    fn into(self) -> ExecutionMethod {
-        ExecutionMethod::ByWindowsHook(self)
-    }
-}
+        if let Some(timeout) = options.get("timeout") {
+            let timeout = timeout.parse().map_err(|_| {
+                Error::InvalidExecutionOption(format!("invalid timeout {}", timeout))
+            })?;
 
-#[repr(C)]
-struct ShellCodeData {
-    status: i32,
-    result: i32,
+            method.timeout = Duration::from_millis(timeout);
+        }
 
-    module_base: u64,
-    reason_for_call: u32,
-    reserved: u64,
+        Ok(method)
+    }
+}
 
-    dllmain: u64,
-    callnexthookex: u64,
+impl ExecutionMethod for ExecutionByWindowsHook {
+    fn call(&self, process: &dyn ProcessTrait, call: RemoteCall) -> Result<EntryResult> {
+        execute_with_windows_hook(process, call, self).map(EntryResult)
+    }
 }
 
 struct ScopedWindowsHookHandle(pub HHOOK);
@@ -73,79 +86,66 @@ impl Drop for ScopedWindowsHookHandle {
     }
 }
 
-struct ScopedRemoteAllocation<'a>(pub u64, &'a dyn ProcessTrait);
+/// Leaks the hook procedure and its data, which a call that is still running returns to and
+/// writes its result into.
+fn leave_running(
+    shellcode_alloc: ScopedRemoteAllocation,
+    shellcode_data_alloc: ScopedRemoteAllocation,
+) -> Error {
+    warn!(
+        "call still running, leaving hook procedure at 0x{:X} and its data at 0x{:X}",
+        shellcode_alloc.0, shellcode_data_alloc.0
+    );
 
-impl Drop for ScopedRemoteAllocation<'_> {
-    fn drop(&mut self) {
-        self.1.mem_free(self.0, None, MEM_RELEASE.0).ok();
-    }
+    std::mem::forget(shellcode_alloc);
+    std::mem::forget(shellcode_data_alloc);
+
+    Error::StillRunning
 }
 
-pub fn execute_with_windows_hook(
+/// Makes `call` from the thread of the target window and returns the function's return value.
+fn execute_with_windows_hook(
     process: &dyn ProcessTrait,
-    module_base: u64,
-    entry_point: u64,
+    call: RemoteCall,
     method: &ExecutionByWindowsHook,
-) -> Result<()> {
+) -> Result<i32> {
     let user32_dll = unsafe {
         LoadLibraryA(PCSTR(
             CString::new("user32.dll").unwrap().as_ptr() as *const _
         ))
     }?;
 
-    let callnexthookex = unsafe {
-        GetProcAddress(
-            user32_dll,
-            PCSTR(CString::new("CallNextHookEx").unwrap().as_ptr() as *const _),
-        )
-    }
-    .ok_or(Error::ImportNotFound)? as u64;
-
     let mut shellcode_data = ShellCodeData {
-        status: 0,
+        status: STATUS_IDLE,
         result: 0,
-        module_base,
-        reason_for_call: DLL_PROCESS_ATTACH,
-        reserved: 0,
-        dllmain: entry_point,
-        callnexthookex,
+        function: call.function,
+        first: call.first,
+        second: call.second,
+        third: call.third,
     };
 
-    let mut shellcode: [u8; 143] = [
-        0x4c, 0x89, 0x44, 0x24, 0x18, 0x48, 0x89, 0x54, 0x24, 0x10, 0x89, 0x4c, 0x24, 0x08, 0x48,
-        0x83, 0xec, 0x38, 0x48, 0xb8, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0x48, 0x89,
-        0x44, 0x24, 0x20, 0x48, 0x8b, 0x44, 0x24, 0x20, 0xb9, 0x01, 0x00, 0x00, 0x00, 0x48, 0x89,
-        0x44, 0x24, 0x28, 0x33, 0xc0, 0x48, 0x8b, 0x54, 0x24, 0x28, 0x48, 0x8b, 0x54, 0x24, 0x28,
-        0xf0, 0x0f, 0xb1, 0x0a, 0x85, 0xc0, 0x74, 0x04, 0x33, 0xc0, 0xeb, 0x42, 0x48, 0x8b, 0x44,
-        0x24, 0x20, 0xc7, 0x00, 0x01, 0x00, 0x00, 0x00, 0x48, 0x8b, 0x44, 0x24, 0x20, 0x4c, 0x8b,
-        0x40, 0x18, 0x48, 0x8b, 0x44, 0x24, 0x20, 0x8b, 0x50, 0x10, 0x48, 0x8b, 0x44, 0x24, 0x20,
-        0x48, 0x8b, 0x48, 0x08, 0x48, 0x8b, 0x44, 0x24, 0x20, 0xff, 0x50, 0x20, 0x48, 0x8b, 0x4c,
-        0x24, 0x20, 0x89, 0x41, 0x04, 0x48, 0x8b, 0x44, 0x24, 0x20, 0xc7, 0x00, 0x02, 0x00, 0x00,
-        0x00, 0x33, 0xc0, 0x48, 0x83, 0xc4, 0x38, 0xc3,
-    ];
-
     let shellcode_data_alloc = ScopedRemoteAllocation(
         process.mem_alloc(
             None,
             std::mem::size_of_val(&shellcode_data),
-            (MEM_RESERVE | MEM_COMMIT).0,
-            PAGE_READWRITE.0,
+            MEM_RESERVE | MEM_COMMIT,
+            PAGE_READWRITE,
         )?,
         process,
     );
 
+    let shellcode = hook_procedure(shellcode_data_alloc.0);
+
     let shellcode_alloc = ScopedRemoteAllocation(
         process.mem_alloc(
             None,
-            std::mem::size_of_val(&shellcode),
-            (MEM_RESERVE | MEM_COMMIT).0,
-            PAGE_READWRITE.0,
+            shellcode.len(),
+            MEM_RESERVE | MEM_COMMIT,
+            PAGE_READWRITE,
         )?,
         process,
     );
 
-    shellcode[20..28].copy_from_slice(&shellcode_data_alloc.0.to_ne_bytes());
-
     process.mem_write(shellcode_alloc.0, &shellcode)?;
 
     process.mem_write(shellcode_data_alloc.0, unsafe {
@@ -155,11 +155,7 @@ pub fn execute_with_windows_hook(
         )
     })?;
 
-    process.mem_protect(
-        shellcode_alloc.0,
-        std::mem::size_of_val(&shellcode),
-        PAGE_EXECUTE_READ.0,
-    )?;
+    process.mem_protect(shellcode_alloc.0, shellcode.len(), PAGE_EXECUTE_READ)?;
 
     let window_handle = unsafe {
         let window_class = method
@@ -196,7 +192,7 @@ pub fn execute_with_windows_hook(
 
     let start_time = Instant::now();
 
-    let _hook_handle = ScopedWindowsHookHandle(unsafe {
+    let hook_handle = ScopedWindowsHookHandle(unsafe {
         SetWindowsHookExA(
             WH_GETMESSAGE,
             Some(std::mem::transmute(shellcode_alloc.0)),
@@ -217,18 +213,33 @@ pub fn execute_with_windows_hook(
             )
         })?;
 
-        if shellcode_data.status != 0 {
+        if shellcode_data.status != STATUS_IDLE {
             break;
         }
 
         if Instant::now() >= start_time + method.timeout {
-            return Err(Error::Timeout);
+            // The hook procedure may have claimed the call since the status was read, and only
+            // stops being called once unhooked.
+            drop(hook_handle);
+
+            process.mem_read(shellcode_data_alloc.0, unsafe {
+                std::slice::from_raw_parts_mut(
+                    &mut shellcode_data as *mut ShellCodeData as *mut _,
+                    std::mem::size_of_val(&shellcode_data),
+                )
+            })?;
+
+            if shellcode_data.status == STATUS_IDLE {
+                return Err(Error::Timeout);
+            }
+
+            return Err(leave_running(shellcode_alloc, shellcode_data_alloc));
         }
 
         sleep(Duration::from_millis(10));
     }
 
-    info!("executing main");
+    info!("executing function");
 
     loop {
         unsafe { PostThreadMessageA(thread_id, WM_NULL, WPARAM(0), LPARAM(0)) }?;
@@ -240,16 +251,16 @@ pub fn execute_with_windows_hook(
             )
         })?;
 
-        if shellcode_data.status == 2 {
+        if shellcode_data.status == STATUS_DONE {
             break;
         }
 
         if Instant::now() >= start_time + method.timeout {
-            return Err(Error::Timeout);
+            return Err(leave_running(shellcode_alloc, shellcode_data_alloc));
         }
 
         sleep(Duration::from_millis(10));
     }
 
-    Ok(())
+    Ok(shellcode_data.result)
 }
@@ -0,0 +1,172 @@
+#[cfg(windows)]
+use std::slice;
+use std::{ffi::CStr, ops::Range};
+#[cfg(windows)]
+use windows::Win32::{
+    Foundation::HMODULE,
+    System::{
+        Diagnostics::Debug::{IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_NT_HEADERS64},
+        SystemServices::{IMAGE_DOS_HEADER, IMAGE_EXPORT_DIRECTORY},
+    },
+};
+
+/// Export directory of a module loaded in this process.
+pub struct ExportTable<'m> {
+    base: usize,
+    directory: Range<u32>,
+    ordinal_base: u32,
+    functions: &'m [u32],
+    names: &'m [u32],
+    name_ordinals: &'m [u16],
+}
+
+impl ExportTable<'_> {
+    /// # Safety
+    ///
+    /// `module` must be a module loaded in this process that stays loaded while the table is used.
+    #[cfg(windows)]
+    pub unsafe fn new(module: HMODULE) -> Option<Self> {
+        let base = module.0 as usize;
+
+        unsafe {
+            let dos_header = &*(base as *const IMAGE_DOS_HEADER);
+            let nt_headers = &*((base + dos_header.e_lfanew as usize) as *const IMAGE_NT_HEADERS64);
+            let data_directory = nt_headers.OptionalHeader.DataDirectory;
+
+            let export_dir = data_directory[IMAGE_DIRECTORY_ENTRY_EXPORT.0 as usize];
+            if export_dir.VirtualAddress == 0 {
+                return None;
+            }
+
+            let exports =
+                &*((base + export_dir.VirtualAddress as usize) as *const IMAGE_EXPORT_DIRECTORY);
+
+            Some(Self {
+                base,
+                directory: export_dir.VirtualAddress
+                    ..export_dir.VirtualAddress.saturating_add(export_dir.Size),
+                ordinal_base: exports.Base,
+                functions: slice::from_raw_parts(
+                    (base + exports.AddressOfFunctions as usize) as *const u32,
+                    exports.NumberOfFunctions as usize,
+                ),
+                names: slice::from_raw_parts(
+                    (base + exports.AddressOfNames as usize) as *const u32,
+                    exports.NumberOfNames as usize,
+                ),
+                name_ordinals: slice::from_raw_parts(
+                    (base + exports.AddressOfNameOrdinals as usize) as *const u16,
+                    exports.NumberOfNames as usize,
+                ),
+            })
+        }
+    }
+
+    /// Looks up a named export, trying the import's hint as an index into the name table before
+    /// a binary search. Returns `None` for forwarded exports.
+    pub fn by_name(&self, hint: usize, name: &str) -> Option<u64> {
+        let name_idx = if self.name_at(hint) == Some(name.as_bytes()) {
+            hint
+        } else {
+            self.names
+                .binary_search_by(|rva| self.name(*rva).cmp(name.as_bytes()))
+                .ok()?
+        };
+
+        self.function(*self.name_ordinals.get(name_idx)? as usize)
+    }
+
+    /// Looks up an export by ordinal. Returns `None` for forwarded exports.
+    pub fn by_ordinal(&self, ordinal: u16) -> Option<u64> {
+        self.function((ordinal as u32).checked_sub(self.ordinal_base)? as usize)
+    }
+
+    fn name(&self, rva: u32) -> &[u8] {
+        unsafe { CStr::from_ptr((self.base + rva as usize) as *const _) }.to_bytes()
+    }
+
+    fn name_at(&self, idx: usize) -> Option<&[u8]> {
+        self.names.get(idx).map(|rva| self.name(*rva))
+    }
+
+    fn function(&self, idx: usize) -> Option<u64> {
+        let rva = *self.functions.get(idx)?;
+
+        // Forwarded exports point at a "module.function" string inside the export directory.
+        if rva == 0 || self.directory.contains(&rva) {
+            return None;
+        }
+
+        Some((self.base + rva as usize) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names as they would sit in a module, at the RVAs listed in `NAMES`.
+    const STRINGS: &[u8] = b"\0Alpha\0Beta\0Delta\0Gamma\0";
+    const NAMES: [u32; 4] = [1, 7, 12, 18];
+
+    fn exports<'m>(strings: &[u8], names: &'m [u32], name_ordinals: &'m [u16]) -> ExportTable<'m> {
+        ExportTable {
+            base: strings.as_ptr() as usize,
+            directory: 0x100..0x200,
+            ordinal_base: 1,
+            functions: &[0x1000, 0x2000, 0x150, 0x4000],
+            names,
+            name_ordinals,
+        }
+    }
+
+    #[test]
+    fn looks_up_names_by_hint() {
+        let exports = exports(STRINGS, &NAMES, &[0, 1, 3, 2]);
+        let base = STRINGS.as_ptr() as u64;
+
+        assert_eq!(exports.by_name(0, "Alpha"), Some(base + 0x1000));
+        assert_eq!(exports.by_name(1, "Beta"), Some(base + 0x2000));
+        assert_eq!(exports.by_name(2, "Delta"), Some(base + 0x4000));
+    }
+
+    #[test]
+    fn searches_names_with_a_wrong_or_stale_hint() {
+        let exports = exports(STRINGS, &NAMES, &[0, 1, 3, 2]);
+        let base = STRINGS.as_ptr() as u64;
+
+        // A hint naming another export, as after the module was updated.
+        assert_eq!(exports.by_name(0, "Delta"), Some(base + 0x4000));
+        assert_eq!(exports.by_name(2, "Beta"), Some(base + 0x2000));
+        // A hint past the end of the name table.
+        assert_eq!(exports.by_name(usize::MAX, "Alpha"), Some(base + 0x1000));
+        assert_eq!(exports.by_name(0, "Missing"), None);
+    }
+
+    #[test]
+    fn trusts_a_matching_hint_over_the_search() {
+        // Out of order, so a binary search for Alpha misses it.
+        let names = [NAMES[3], NAMES[1], NAMES[2], NAMES[0]];
+        let exports = exports(STRINGS, &names, &[3, 1, 2, 0]);
+
+        assert_eq!(
+            exports.by_name(3, "Alpha"),
+            Some(STRINGS.as_ptr() as u64 + 0x1000)
+        );
+        assert_eq!(exports.by_name(0, "Alpha"), None);
+    }
+
+    #[test]
+    fn leaves_forwarded_exports_to_the_loader() {
+        let exports = exports(STRINGS, &NAMES, &[0, 1, 3, 2]);
+
+        // Gamma points into the export directory.
+        assert_eq!(exports.by_name(3, "Gamma"), None);
+        assert_eq!(exports.by_ordinal(3), None);
+        assert_eq!(
+            exports.by_ordinal(4),
+            Some(STRINGS.as_ptr() as u64 + 0x4000)
+        );
+        assert_eq!(exports.by_ordinal(0), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{dto, error::Result, process::ProcessTrait};
+
+/// `IMAGE_DOS_HEADER::e_lfanew`
+const E_LFANEW: u64 = 0x3C;
+/// `IMAGE_NT_HEADERS64::OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG]`
+const LOAD_CONFIG_DIRECTORY: u64 = 0x18 + 0x70 + 10 * 8;
+
+/// Fields of `IMAGE_LOAD_CONFIG_DIRECTORY64`.
+const LOAD_CONFIG_SIZE: u64 = 0x00;
+const GUARD_CF_CHECK_FUNCTION_POINTER: u64 = 0x70;
+const GUARD_CF_DISPATCH_FUNCTION_POINTER: u64 = 0x78;
+const GUARD_FLAGS: u64 = 0x90;
+
+const IMAGE_GUARD_CF_INSTRUMENTED: u32 = 0x100;
+
+#[cfg(windows)]
+const _: () = {
+    use std::mem::offset_of;
+    use windows::Win32::System::{
+        Diagnostics::Debug::{
+            IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_LOAD_CONFIG_DIRECTORY64, IMAGE_NT_HEADERS64,
+            IMAGE_OPTIONAL_HEADER64,
+        },
+        SystemServices::IMAGE_DOS_HEADER,
+    };
+
+    assert!(offset_of!(IMAGE_DOS_HEADER, e_lfanew) as u64 == E_LFANEW);
+    assert!(
+        (offset_of!(IMAGE_NT_HEADERS64, OptionalHeader)
+            + offset_of!(IMAGE_OPTIONAL_HEADER64, DataDirectory)
+            + IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG.0 as usize * 8) as u64
+            == LOAD_CONFIG_DIRECTORY
+    );
+    assert!(offset_of!(IMAGE_LOAD_CONFIG_DIRECTORY64, Size) as u64 == LOAD_CONFIG_SIZE);
+    assert!(
+        offset_of!(IMAGE_LOAD_CONFIG_DIRECTORY64, GuardCFCheckFunctionPointer) as u64
+            == GUARD_CF_CHECK_FUNCTION_POINTER
+    );
+    assert!(
+        offset_of!(
+            IMAGE_LOAD_CONFIG_DIRECTORY64,
+            GuardCFDispatchFunctionPointer
+        ) as u64
+            == GUARD_CF_DISPATCH_FUNCTION_POINTER
+    );
+    assert!(offset_of!(IMAGE_LOAD_CONFIG_DIRECTORY64, GuardFlags) as u64 == GUARD_FLAGS);
+    assert!(
+        windows::Win32::System::SystemServices::IMAGE_GUARD_CF_INSTRUMENTED
+            == IMAGE_GUARD_CF_INSTRUMENTED
+    );
+};
+
+fn read_u32(process: &dyn ProcessTrait, address: u64) -> Result<u32> {
+    let mut value = [0u8; 4];
+    process.mem_read(address, &mut value)?;
+    Ok(u32::from_le_bytes(value))
+}
+
+fn read_u64(process: &dyn ProcessTrait, address: u64) -> Result<u64> {
+    let mut value = [0u8; 8];
+    process.mem_read(address, &mut value)?;
+    Ok(u64::from_le_bytes(value))
+}
+
+/// Base of kernel32 in the target. System DLLs are mapped at the same base in every process, so
+/// it is looked up in this one.
+#[cfg(windows)]
+pub fn kernel32_base() -> Option<u64> {
+    use windows::{Win32::System::LibraryLoader::GetModuleHandleA, core::s};
+
+    let module = unsafe { GetModuleHandleA(s!("kernel32.dll")) }.ok()?;
+    Some(module.0 as u64)
+}
+
+/// Without a local kernel32 the target's cannot be found.
+#[cfg(not(windows))]
+pub fn kernel32_base() -> Option<u64> {
+    None
+}
+
+/// Reads the Control Flow Guard functions the loader stored through kernel32's load config in the
+/// target, which differ from this process's if the target runs with other mitigations.
+pub fn get_guard_cf_functions(
+    process: &dyn ProcessTrait,
+    kernel32_base: u64,
+) -> Result<Option<dto::GuardCfFunctions>> {
+    let nt_headers = kernel32_base + read_u32(process, kernel32_base + E_LFANEW)? as u64;
+
+    let load_config_rva = read_u32(process, nt_headers + LOAD_CONFIG_DIRECTORY)?;
+    if load_config_rva == 0 {
+        return Ok(None);
+    }
+
+    let load_config = kernel32_base + load_config_rva as u64;
+
+    let size = read_u32(process, load_config + LOAD_CONFIG_SIZE)? as u64;
+    if size < GUARD_FLAGS + 4
+        || read_u32(process, load_config + GUARD_FLAGS)? & IMAGE_GUARD_CF_INSTRUMENTED == 0
+    {
+        return Ok(None);
+    }
+
+    let check_function_pointer = read_u64(process, load_config + GUARD_CF_CHECK_FUNCTION_POINTER)?;
+    let dispatch_function_pointer =
+        read_u64(process, load_config + GUARD_CF_DISPATCH_FUNCTION_POINTER)?;
+    if check_function_pointer == 0 || dispatch_function_pointer == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(dto::GuardCfFunctions {
+        check_function: read_u64(process, check_function_pointer)?,
+        dispatch_function: read_u64(process, dispatch_function_pointer)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{MEM_COMMIT, MEM_RESERVE, MockProcess, PAGE_READWRITE};
+
+    const CHECK_FUNCTION: u64 = 0x7FFA_1111_0000;
+    const DISPATCH_FUNCTION: u64 = 0x7FFA_2222_0000;
+
+    struct Kernel32 {
+        process: MockProcess,
+        base: u64,
+    }
+
+    impl Kernel32 {
+        /// Lays out the headers, a load config at +0x1000 and the function pointers at +0x2000.
+        fn new() -> Self {
+            let mut process = MockProcess::new(1);
+            process.attach().unwrap();
+
+            let base = process
+                .mem_alloc(None, 0x3000, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE)
+                .unwrap();
+            let kernel32 = Self { process, base };
+
+            kernel32.write_u32(E_LFANEW, 0x80);
+            kernel32.write_u32(0x80 + LOAD_CONFIG_DIRECTORY, 0x1000);
+            kernel32.write_u32(0x1000 + LOAD_CONFIG_SIZE, 0x140);
+            kernel32.write_u64(0x1000 + GUARD_CF_CHECK_FUNCTION_POINTER, base + 0x2000);
+            kernel32.write_u64(0x1000 + GUARD_CF_DISPATCH_FUNCTION_POINTER, base + 0x2008);
+            kernel32.write_u32(0x1000 + GUARD_FLAGS, IMAGE_GUARD_CF_INSTRUMENTED | 0x400);
+            kernel32.write_u64(0x2000, CHECK_FUNCTION);
+            kernel32.write_u64(0x2008, DISPATCH_FUNCTION);
+
+            kernel32
+        }
+
+        fn write_u32(&self, offset: u64, value: u32) {
+            self.process
+                .mem_write(self.base + offset, &value.to_le_bytes())
+                .unwrap();
+        }
+
+        fn write_u64(&self, offset: u64, value: u64) {
+            self.process
+                .mem_write(self.base + offset, &value.to_le_bytes())
+                .unwrap();
+        }
+
+        fn guard_cf_functions(&self) -> Result<Option<dto::GuardCfFunctions>> {
+            get_guard_cf_functions(&self.process, self.base)
+        }
+    }
+
+    #[test]
+    fn reads_the_functions_from_the_target() {
+        let functions = Kernel32::new().guard_cf_functions().unwrap().unwrap();
+
+        assert_eq!(functions.check_function, CHECK_FUNCTION);
+        assert_eq!(functions.dispatch_function, DISPATCH_FUNCTION);
+    }
+
+    #[test]
+    fn skips_images_without_guard_functions() {
+        let kernel32 = Kernel32::new();
+        kernel32.write_u32(0x1000 + GUARD_FLAGS, 0x400);
+        assert!(kernel32.guard_cf_functions().unwrap().is_none());
+
+        // A load config that ends before GuardFlags.
+        let kernel32 = Kernel32::new();
+        kernel32.write_u32(0x1000 + LOAD_CONFIG_SIZE, GUARD_FLAGS as u32);
+        assert!(kernel32.guard_cf_functions().unwrap().is_none());
+
+        let kernel32 = Kernel32::new();
+        kernel32.write_u64(0x1000 + GUARD_CF_DISPATCH_FUNCTION_POINTER, 0);
+        assert!(kernel32.guard_cf_functions().unwrap().is_none());
+
+        let kernel32 = Kernel32::new();
+        kernel32.write_u32(0x80 + LOAD_CONFIG_DIRECTORY, 0);
+        assert!(kernel32.guard_cf_functions().unwrap().is_none());
+    }
+
+    #[test]
+    fn fails_on_unreadable_memory() {
+        let kernel32 = Kernel32::new();
+        kernel32.write_u64(0x1000 + GUARD_CF_CHECK_FUNCTION_POINTER, 0x1000);
+
+        assert!(kernel32.guard_cf_functions().is_err());
+    }
+}
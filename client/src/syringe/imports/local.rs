@@ -0,0 +1,82 @@
+use crate::{
+    error::{Error, Result},
+    syringe::{exports::ExportTable, imports::ImportResolver},
+};
+use net_syringe_common::fixups::{ImportName, normalize_module_name};
+use std::{cell::RefCell, collections::HashMap, ffi::CString};
+use windows::{
+    Win32::{
+        Foundation::HMODULE,
+        System::LibraryLoader::{GetProcAddress, LoadLibraryA},
+    },
+    core::PCSTR,
+};
+
+fn get_export_by_name(module_handle: HMODULE, hint: usize, name: &str) -> Result<u64> {
+    if let Some(address) =
+        unsafe { ExportTable::new(module_handle) }.and_then(|exports| exports.by_name(hint, name))
+    {
+        return Ok(address);
+    }
+
+    // Forwarded exports are left to the system loader.
+    let import_name = CString::new(name.as_bytes()).map_err(|_| Error::ImportNotFound)?;
+    let procedure = unsafe { GetProcAddress(module_handle, PCSTR(import_name.as_ptr() as _)) }
+        .ok_or(Error::ImportNotFound)?;
+
+    Ok(procedure as usize as u64)
+}
+
+fn get_export_by_ordinal(module_handle: HMODULE, ordinal: u16) -> Result<u64> {
+    if ordinal == 0 {
+        return Err(Error::ImportNotFound);
+    }
+
+    if let Some(address) =
+        unsafe { ExportTable::new(module_handle) }.and_then(|exports| exports.by_ordinal(ordinal))
+    {
+        return Ok(address);
+    }
+
+    let procedure = unsafe { GetProcAddress(module_handle, PCSTR(ordinal as usize as *const u8)) }
+        .ok_or(Error::ImportNotFound)?;
+
+    Ok(procedure as usize as u64)
+}
+
+/// Resolves imports by loading each imported module once in this process. System DLLs are mapped
+/// at the same base in every process, so their addresses are valid in the target as well.
+#[derive(Default)]
+pub struct LocalImportResolver {
+    /// Loaded modules by normalized name.
+    modules: RefCell<HashMap<String, HMODULE>>,
+}
+
+impl LocalImportResolver {
+    fn module(&self, dll_name: &str) -> Result<HMODULE> {
+        let module_key = normalize_module_name(dll_name);
+
+        if let Some(module_handle) = self.modules.borrow().get(&module_key) {
+            return Ok(*module_handle);
+        }
+
+        let c_dll_name = CString::new(dll_name.as_bytes()).map_err(|_| Error::ImportNotFound)?;
+        let module_handle = unsafe { LoadLibraryA(PCSTR(c_dll_name.as_ptr() as _)) }?;
+
+        self.modules.borrow_mut().insert(module_key, module_handle);
+        Ok(module_handle)
+    }
+}
+
+impl ImportResolver for LocalImportResolver {
+    fn resolve(&self, dll_name: &str, import: &ImportName) -> Result<u64> {
+        let module_handle = self.module(dll_name)?;
+
+        match import {
+            ImportName::Name { hint, name } => {
+                get_export_by_name(module_handle, *hint as usize, name)
+            }
+            ImportName::Ordinal(ordinal) => get_export_by_ordinal(module_handle, *ordinal),
+        }
+    }
+}
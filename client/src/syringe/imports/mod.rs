@@ -0,0 +1,59 @@
+use crate::{dto, error::Result};
+use net_syringe_common::fixups::ImportName;
+use std::collections::HashMap;
+
+#[cfg(windows)]
+pub use local::LocalImportResolver;
+
+#[cfg(windows)]
+mod local;
+
+/// Resolves the addresses of imported functions in the target process.
+pub trait ImportResolver {
+    fn resolve(&self, dll_name: &str, import: &ImportName) -> Result<u64>;
+}
+
+impl<F: Fn(&str, &ImportName) -> Result<u64>> ImportResolver for F {
+    fn resolve(&self, dll_name: &str, import: &ImportName) -> Result<u64> {
+        self(dll_name, import)
+    }
+}
+
+/// [`LocalImportResolver`] on Windows. Elsewhere there is nothing to resolve imports against and
+/// every import fails until a resolver is supplied.
+pub fn default_import_resolver() -> Box<dyn ImportResolver> {
+    #[cfg(windows)]
+    return Box::new(LocalImportResolver::default());
+
+    #[cfg(not(windows))]
+    Box::new(|_: &str, _: &ImportName| Err(crate::error::Error::ImportNotFound))
+}
+
+/// Fills in the address of every import the service reported.
+pub fn resolve_imports(
+    resolver: &dyn ImportResolver,
+    mut imports: HashMap<String, Vec<dto::Import>>,
+) -> Result<HashMap<String, Vec<dto::Import>>> {
+    for (dll_name, imports) in &mut imports {
+        for import in imports {
+            match import {
+                dto::Import::ByName {
+                    hint,
+                    name,
+                    address,
+                } => {
+                    let hint = u16::try_from(*hint).unwrap_or_default();
+                    let import = ImportName::Name {
+                        hint,
+                        name: name.clone(),
+                    };
+                    *address = Some(resolver.resolve(dll_name, &import)?);
+                }
+                dto::Import::ByOrdinal { ordinal, address } => {
+                    *address = Some(resolver.resolve(dll_name, &ImportName::Ordinal(*ordinal))?);
+                }
+            }
+        }
+    }
+    Ok(imports)
+}
@@ -1,112 +1,982 @@
 use crate::{
     dto::{self, GetLibraryMapping},
     error::{Error, Result},
-    process::ProcessTrait,
-    syringe::{execution::execute_with_windows_hook, imports::resolve_imports},
+    process::{MEM_COMMIT, MEM_DECOMMIT, MEM_RESERVE, PAGE_READWRITE, ProcessTrait},
+    syringe::{
+        execution::{DLL_PROCESS_ATTACH, ScopedRemoteAllocation},
+        imports::{default_import_resolver, resolve_imports},
+        transaction::{Action, Transaction, undo},
+    },
 };
-use log::info;
-use reqwest::blocking::Client as HttpClient;
-use windows::Win32::System::{
-    Diagnostics::Debug::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_WRITE},
-    Memory::{MEM_COMMIT, MEM_RESERVE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_READWRITE},
+use log::{debug, error, info, warn};
+use net_syringe_common::{
+    envelope::{self, DiscardableSection, Envelope},
+    fixups::ImportName,
+    protection::{plan_decommit, plan_protections},
 };
+use reqwest::{
+    StatusCode,
+    blocking::{Client as HttpClient, RequestBuilder, Response},
+    header,
+};
+use std::str::FromStr;
 
 mod execution;
+#[cfg(any(windows, test))]
+mod exports;
+mod guard_cf;
 mod imports;
+mod reload;
+#[cfg(test)]
+mod testing;
+mod transaction;
+
+#[cfg(windows)]
+pub use execution::ExecutionByWindowsHook;
+pub use execution::{EntryResult, ExecutionMethod, ExecutionMethods, ExecutionOptions, RemoteCall};
+pub use imports::ImportResolver;
+#[cfg(windows)]
+pub use imports::LocalImportResolver;
 
-pub use execution::{ExecutionByWindowsHook, ExecutionMethod};
+/// Size of a `RUNTIME_FUNCTION` entry in the exception table.
+const RUNTIME_FUNCTION_SIZE: u32 = 12;
 
+/// Injects libraries from the service into a process, configured with builder methods.
 pub struct Syringe<'p> {
     process: &'p dyn ProcessTrait,
+    import_resolver: Box<dyn ImportResolver + 'p>,
     http_client: HttpClient,
     api_url: String,
+    base_independent: bool,
+    keep_on_failure: bool,
+    discard_sections: DiscardSections,
+}
+
+/// Discardable sections to decommit once the library is initialized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiscardSections {
+    None,
+    /// Only `.reloc`, which is not used once the image is relocated.
+    #[default]
+    Relocations,
+    All,
+}
+
+impl DiscardSections {
+    fn includes(&self, section: &DiscardableSection) -> bool {
+        match self {
+            DiscardSections::None => false,
+            DiscardSections::Relocations => section.name == ".reloc",
+            DiscardSections::All => true,
+        }
+    }
+}
+
+impl FromStr for DiscardSections {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(DiscardSections::None),
+            "relocations" => Ok(DiscardSections::Relocations),
+            "all" => Ok(DiscardSections::All),
+            _ => Err(format!(
+                "unknown value \"{}\", expected none, relocations or all",
+                value
+            )),
+        }
+    }
+}
+
+/// Everything an injection created in the target, for [`Syringe::eject`].
+#[derive(Debug, Clone)]
+pub struct InjectedModule {
+    pub module_base: u64,
+    actions: Vec<Action>,
+    /// Set when a step of an ejection timed out while running, after which the module is never
+    /// freed.
+    left_running: bool,
+}
+
+/// Outcome of a successful injection.
+#[derive(Debug, Clone)]
+pub struct InjectionReport {
+    pub module: InjectedModule,
+    /// Value returned by `DllMain`, `None` for libraries without an entry point.
+    pub entry_point_result: Option<i32>,
+    /// Value returned by the init export, if one was called.
+    pub init_result: Option<i32>,
+}
+
+/// An exported function called after `DllMain` as
+/// `int export(const void *payload, unsigned int payload_size, HMODULE module)`.
+#[derive(Debug, Default)]
+pub struct InitCall {
+    pub export: String,
+    /// Written to the target for the duration of the call, passed as a null pointer if empty.
+    pub payload: Vec<u8>,
 }
 
 impl<'p> Syringe<'p> {
+    /// Injects into `process`, an attached process, using the service at `api_url`.
     pub fn new<S: Into<String>>(process: &'p dyn ProcessTrait, api_url: S) -> Self {
         let http_client = HttpClient::new();
 
         Self {
             process,
+            import_resolver: default_import_resolver(),
             http_client,
             api_url: api_url.into(),
+            base_independent: false,
+            keep_on_failure: false,
+            discard_sections: DiscardSections::default(),
         }
     }
 
-    pub fn inject(&self, library_id: &str, execution_method: &ExecutionMethod) -> Result<()> {
-        let response = self
-            .http_client
-            .get(format!("{}/libraries/{}", self.api_url, library_id))
-            .send()?;
+    /// Resolves imports with `resolver` instead of the default [`LocalImportResolver`].
+    pub fn import_resolver<R: ImportResolver + 'p>(mut self, resolver: R) -> Self {
+        self.import_resolver = Box::new(resolver);
+        self
+    }
+
+    /// Fetches the image in a single request and applies relocations and imports locally,
+    /// instead of letting the service map it for the allocation base.
+    pub fn base_independent(mut self, enabled: bool) -> Self {
+        self.base_independent = enabled;
+        self
+    }
+
+    /// Leaves the allocations and attached library of a failed injection in place for debugging,
+    /// instead of rolling them back.
+    pub fn keep_on_failure(mut self, enabled: bool) -> Self {
+        self.keep_on_failure = enabled;
+        self
+    }
+
+    /// Selects the discardable sections decommitted after initialization, `.reloc` by default.
+    pub fn discard_sections(mut self, sections: DiscardSections) -> Self {
+        self.discard_sections = sections;
+        self
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        let features = dto::SUPPORTED_FEATURES
+            .iter()
+            .map(|feature| feature.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
 
-        if !response.status().is_success() {
-            return Err(Error::HttpClientError);
+        self.http_client
+            .get(format!("{}{}", self.api_url, path))
+            .header(dto::PROTOCOL_VERSION_HEADER, dto::PROTOCOL_VERSION)
+            .header(dto::PROTOCOL_FEATURES_HEADER, features)
+    }
+
+    fn check_response(response: Response) -> Result<Response> {
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            StatusCode::PRECONDITION_FAILED | StatusCode::UNPROCESSABLE_ENTITY => {
+                let message = response.text()?;
+                error!("service refused the request: {}", message);
+                Err(Error::IncompatibleService(message))
+            }
+            _ => Err(Error::HttpClientError),
         }
+    }
 
-        let library: dto::GetLibraryResponse = response.json()?;
+    fn get_library(&self, library_id: &str) -> Result<dto::GetLibraryResponse> {
+        let response =
+            Self::check_response(self.get(&format!("/libraries/{}", library_id)).send()?)?;
 
-        info!("resolving imports");
+        Ok(response.json()?)
+    }
 
-        let imports = resolve_imports(library.imports)?;
+    /// Control Flow Guard functions of the target, for libraries built with `/guard:cf`.
+    fn get_guard_cf_functions(&self) -> Result<Option<dto::GuardCfFunctions>> {
+        match guard_cf::kernel32_base() {
+            Some(kernel32_base) => guard_cf::get_guard_cf_functions(self.process, kernel32_base),
+            None => Ok(None),
+        }
+    }
 
-        info!("allocating space for image");
+    /// Allocates the image first, then has the service relocate it and resolve its imports.
+    fn map_on_service(
+        &self,
+        library_id: &str,
+        transaction: &mut Transaction,
+    ) -> Result<(u64, Envelope)> {
+        let library = self.get_library(library_id)?;
 
-        let allocation = self.process.mem_alloc(
-            None,
+        info!("resolving imports");
+
+        let imports = resolve_imports(self.import_resolver.as_ref(), library.imports)?;
+
+        let allocation = self.allocate_image(
+            transaction,
             library.size_of_image as usize,
-            (MEM_RESERVE | MEM_COMMIT).0,
-            PAGE_READWRITE.0,
+            library.relocs_stripped.then_some(library.image_base),
+        )?;
+
+        let response = Self::check_response(
+            self.get(&format!("/libraries/{}/mapping", library_id))
+                .header(header::ACCEPT, envelope::CONTENT_TYPE)
+                .json(&GetLibraryMapping {
+                    allocation_base: allocation,
+                    imports,
+                    guard_cf: self.get_guard_cf_functions()?,
+                })
+                .send()?,
         )?;
-        
-        info!("allocated space for image at 0x{:X} (size: 0x{:X})", allocation, library.size_of_image);
 
+        let is_envelope = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(envelope::CONTENT_TYPE));
+
+        // Services without envelope support respond with the raw image bytes.
+        let mapping = if is_envelope {
+            envelope::decode(&response.bytes()?)?
+        } else {
+            Envelope {
+                entry_point: library.entry_point,
+                regions: library
+                    .regions
+                    .into_iter()
+                    .map(|region| envelope::Region {
+                        rva: region.rva,
+                        size: region.size,
+                        characteristics: region.characteristics,
+                    })
+                    .collect(),
+                tls_callbacks: Vec::new(),
+                exception_table: None,
+                fixups: None,
+                discardable: library
+                    .discardable
+                    .into_iter()
+                    .map(|section| envelope::DiscardableSection {
+                        name: section.name,
+                        rva: section.rva,
+                        size: section.size,
+                    })
+                    .collect(),
+                image: response.bytes()?.to_vec(),
+            }
+        };
+
+        Ok((allocation, mapping))
+    }
+
+    /// Fetches a base-independent image in one request and applies its fixups locally.
+    fn map_base_independent(
+        &self,
+        library_id: &str,
+        transaction: &mut Transaction,
+    ) -> Result<(u64, Envelope)> {
+        let mut request = self
+            .get(&format!("/libraries/{}/image", library_id))
+            .header(header::ACCEPT, envelope::CONTENT_TYPE);
+
+        if let Some(guard_cf) = self.get_guard_cf_functions()? {
+            request = request.query(&[
+                ("guard_cf_check_function", guard_cf.check_function),
+                ("guard_cf_dispatch_function", guard_cf.dispatch_function),
+            ]);
+        }
+
+        let response = Self::check_response(request.send()?)?;
+
+        let mut mapping = envelope::decode(&response.bytes()?)?;
+        let fixups = mapping.fixups.take().ok_or(Error::InvalidEnvelope)?;
+
+        info!("resolving imports");
+
+        fixups.apply_imports(&mut mapping.image, |fixup| {
+            self.import_resolver
+                .resolve(&fixup.module, &fixup.import)
+                .ok()
+        })?;
+
+        let allocation = self.allocate_image(
+            transaction,
+            mapping.image.len(),
+            fixups.relocs_stripped.then_some(fixups.image_base),
+        )?;
+
+        info!("relocating image");
+
+        fixups.apply_relocations(&mut mapping.image, allocation)?;
+
+        Ok((allocation, mapping))
+    }
+
+    fn get_export(&self, library_id: &str, name: &str) -> Result<u32> {
         let response = self
-            .http_client
-            .get(format!("{}/libraries/{}/mapping", self.api_url, library_id))
-            .json(&GetLibraryMapping {
-                allocation_base: allocation,
-                imports,
-            })
+            .get(&format!("/libraries/{}/export", library_id))
+            .query(&[("name", name)])
             .send()?;
 
-        if !response.status().is_success() {
-            return Err(Error::HttpClientError);
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Error::ExportNotFound(name.to_string()));
         }
 
-        let mapping = response.bytes()?;
+        let export: dto::GetLibraryExportResponse = Self::check_response(response)?.json()?;
+
+        Ok(export.rva)
+    }
+
+    /// Allocates the image, at `preferred_base` if it cannot be relocated.
+    fn allocate_image(
+        &self,
+        transaction: &mut Transaction,
+        size_of_image: usize,
+        preferred_base: Option<u64>,
+    ) -> Result<u64> {
+        info!("allocating space for image");
+
+        if let Some(base) = preferred_base {
+            info!(
+                "image has no relocations, allocating at its preferred base 0x{:X}",
+                base
+            );
+        }
+
+        let allocation = self.process.mem_alloc(
+            preferred_base,
+            size_of_image,
+            MEM_RESERVE | MEM_COMMIT,
+            PAGE_READWRITE,
+        )?;
+
+        transaction.record(Action::Allocation(allocation));
+
+        info!(
+            "allocated space for image at 0x{:X} (size: 0x{:X})",
+            allocation, size_of_image
+        );
+
+        Ok(allocation)
+    }
+
+    /// Maps the library, calls its entry point and then `init` if given.
+    ///
+    /// Everything done to the target is rolled back if the injection fails. If the entry point
+    /// returns FALSE the library is detached and freed, like the Windows loader does, and
+    /// [`Error::EntryPointFailed`] is returned. If the entry point or export times out while
+    /// running, nothing is rolled back and [`Error::LibraryLeftRunning`] is returned.
+    pub fn inject(
+        &self,
+        library_id: &str,
+        execution_method: &dyn ExecutionMethod,
+        init: Option<&InitCall>,
+    ) -> Result<InjectionReport> {
+        // Resolved before anything is written to the process, so a missing export fails early.
+        let init_export = init
+            .map(|init| Ok::<_, Error>((init, self.get_export(library_id, &init.export)?)))
+            .transpose()?;
+
+        let mut transaction =
+            Transaction::new(self.process, execution_method, self.keep_on_failure);
+
+        let (allocation, mapping) = if self.base_independent {
+            self.map_base_independent(library_id, &mut transaction)?
+        } else {
+            self.map_on_service(library_id, &mut transaction)?
+        };
 
         info!("writing image to process");
 
-        self.process.mem_write(allocation, &mapping)?;
+        self.process.mem_write(allocation, &mapping.image)?;
 
         info!("applying protections");
 
-        for region in &library.regions {
-            let mut protect = PAGE_READWRITE;
-            if (region.characteristics & IMAGE_SCN_MEM_EXECUTE.0) != 0 {
-                if (region.characteristics & IMAGE_SCN_MEM_WRITE.0) != 0 {
-                    protect = PAGE_EXECUTE_READWRITE;
-                } else {
-                    protect = PAGE_EXECUTE_READ;
+        let image_size = mapping.image.len() as u32;
+        for range in plan_protections(image_size, &mapping.regions) {
+            self.process.mem_protect(
+                allocation + range.rva as u64,
+                range.size as usize,
+                range.protection,
+            )?;
+        }
+
+        if let Some(exception_table) = mapping.exception_table {
+            match self.register_exception_table(
+                &mut transaction,
+                allocation,
+                exception_table,
+                execution_method,
+            ) {
+                Err(Error::StillRunning) => return Err(transaction.abandon(allocation)),
+                result => result?,
+            }
+        }
+
+        let entry_point_result = match mapping.entry_point {
+            Some(entry_point) => {
+                info!("calling entry point");
+
+                let entry_point = allocation + entry_point as u64;
+                let result = match execution_method.execute(
+                    self.process,
+                    allocation,
+                    entry_point,
+                    DLL_PROCESS_ATTACH,
+                ) {
+                    Err(Error::StillRunning) => return Err(transaction.abandon(allocation)),
+                    result => result?,
+                };
+
+                // Detached on rollback even if it failed, like the Windows loader does.
+                transaction.record(Action::Attached {
+                    module_base: allocation,
+                    entry_point,
+                });
+
+                if !result.succeeded() {
+                    error!("entry point returned FALSE, unloading library");
+                    return Err(Error::EntryPointFailed);
                 }
+
+                Some(result.0)
+            }
+            None => {
+                info!("library has no entry point, skipping DllMain");
+                None
             }
+        };
 
-            self.process.mem_protect(
-                allocation + region.rva as u64,
-                region.size as usize,
-                protect.0,
-            )?;
+        let init_result = match init_export
+            .map(|(init, rva)| self.call_export(allocation, rva, init, execution_method))
+            .transpose()
+        {
+            Err(Error::StillRunning) => return Err(transaction.abandon(allocation)),
+            result => result?,
+        };
+
+        self.decommit_discardable(allocation, &mapping);
+
+        Ok(InjectionReport {
+            module: InjectedModule {
+                module_base: allocation,
+                actions: transaction.commit(),
+                left_running: false,
+            },
+            entry_point_result,
+            init_result,
+        })
+    }
+
+    /// Decommits the selected discardable sections. Failures are only logged, the library is
+    /// already initialized.
+    fn decommit_discardable(&self, allocation: u64, mapping: &Envelope) {
+        let discarded = mapping
+            .discardable
+            .iter()
+            .filter(|section| self.discard_sections.includes(section))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if discarded.is_empty() {
+            return;
         }
 
-        match execution_method {
-            ExecutionMethod::ByWindowsHook(method) => execute_with_windows_hook(
+        let image_size = mapping.image.len() as u32;
+        for (rva, size) in plan_decommit(image_size, &mapping.regions, &discarded) {
+            debug!("decommitting 0x{:X} (size: 0x{:X})", rva, size);
+
+            if let Err(err) =
+                self.process
+                    .mem_free(allocation + rva as u64, Some(size as usize), MEM_DECOMMIT)
+            {
+                warn!(
+                    "failed to decommit discardable pages at 0x{:X}: {}",
+                    rva, err
+                );
+            }
+        }
+    }
+
+    /// Registers the exception table at `rva` with `RtlAddFunctionTable`, as the Windows loader
+    /// does, so exceptions can be dispatched and unwound through the library.
+    fn register_exception_table(
+        &self,
+        transaction: &mut Transaction,
+        allocation: u64,
+        (rva, size): (u32, u32),
+        execution_method: &dyn ExecutionMethod,
+    ) -> Result<()> {
+        let resolve = |name: &str| {
+            self.import_resolver.resolve(
+                "ntdll.dll",
+                &ImportName::Name {
+                    hint: 0,
+                    name: name.to_string(),
+                },
+            )
+        };
+        let add_function = resolve("RtlAddFunctionTable")?;
+        let delete_function = resolve("RtlDeleteFunctionTable")?;
+
+        let function_table = allocation + rva as u64;
+
+        info!(
+            "registering exception table at 0x{:X} (size: 0x{:X})",
+            function_table, size
+        );
+
+        let result = execution_method.call(
+            self.process,
+            RemoteCall {
+                function: add_function,
+                first: function_table,
+                second: size / RUNTIME_FUNCTION_SIZE,
+                third: allocation,
+            },
+        )?;
+
+        if !result.succeeded_boolean() {
+            return Err(Error::ExceptionTableFailed);
+        }
+
+        transaction.record(Action::ExceptionTable {
+            function_table,
+            delete_function,
+        });
+
+        Ok(())
+    }
+
+    /// Writes the payload of `init` to the target and calls the export with a pointer to it.
+    fn call_export(
+        &self,
+        allocation: u64,
+        rva: u32,
+        init: &InitCall,
+        execution_method: &dyn ExecutionMethod,
+    ) -> Result<i32> {
+        let payload = if init.payload.is_empty() {
+            None
+        } else {
+            let payload = ScopedRemoteAllocation(
+                self.process.mem_alloc(
+                    None,
+                    init.payload.len(),
+                    MEM_RESERVE | MEM_COMMIT,
+                    PAGE_READWRITE,
+                )?,
                 self.process,
-                allocation,
-                allocation + library.entry_point as u64,
-                method,
-            )?,
+            );
+
+            self.process.mem_write(payload.0, &init.payload)?;
+
+            Some(payload)
+        };
+
+        info!("calling export {}", init.export);
+
+        let result = execution_method.call(
+            self.process,
+            RemoteCall {
+                function: allocation + rva as u64,
+                first: payload.as_ref().map_or(0, |payload| payload.0),
+                second: init.payload.len() as u32,
+                third: allocation,
+            },
+        );
+
+        // The export may still read the payload.
+        if let Err(Error::StillRunning) = result {
+            std::mem::forget(payload);
+        }
+
+        let result = result?;
+
+        Ok(result.0)
+    }
+
+    /// Ejects a library injected by [`Syringe::inject`], calling its entry point with
+    /// `DLL_PROCESS_DETACH`, unregistering its exception table and freeing it. Stops at the first
+    /// step that fails and leaves the rest in `module`, so ejecting it again continues from there.
+    /// If a step times out while running, the library is left in place and
+    /// [`Error::LibraryLeftRunning`] is returned, then and on every later attempt, since freeing
+    /// it could crash the target. Libraries with TLS callbacks are refused by the service, so
+    /// there are no detach callbacks to run.
+    pub fn eject(
+        &self,
+        module: &mut InjectedModule,
+        execution_method: &dyn ExecutionMethod,
+    ) -> Result<()> {
+        if module.left_running {
+            return Err(Error::LibraryLeftRunning(module.module_base));
+        }
+
+        info!("ejecting library at 0x{:X}", module.module_base);
+
+        while let Some(action) = module.actions.last() {
+            match undo(self.process, action, execution_method) {
+                Ok(()) => {}
+                Err(Error::StillRunning) => {
+                    module.actions.pop();
+                    module.left_running = true;
+
+                    warn!(
+                        "call into library at 0x{:X} is still running, leaving it in place: {:?}",
+                        module.module_base, module.actions
+                    );
+                    return Err(Error::LibraryLeftRunning(module.module_base));
+                }
+                Err(err) => return Err(err),
+            }
+
+            module.actions.pop();
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process::{self, MockProcess, PAGE_EXECUTE_READ, PAGE_READONLY},
+        syringe::{
+            execution::DLL_PROCESS_DETACH,
+            testing::{StubExecution, service_url},
+        },
+    };
+
+    /// `service/fixtures/lld-link.dll`, see the README there.
+    const LIBRARY: &str = "lld-link.dll";
+    const LIBRARY_IMAGE_BASE: u64 = 0x1_8000_0000;
+    const LIBRARY_SIZE: u64 = 0x6000;
+
+    const RTL_ADD_FUNCTION_TABLE: u64 = 0x7FF0_0000_1000;
+    const RTL_DELETE_FUNCTION_TABLE: u64 = 0x7FF0_0000_2000;
+
+    fn resolve_ntdll(dll_name: &str, import: &ImportName) -> Result<u64> {
+        match (dll_name, import) {
+            ("ntdll.dll", ImportName::Name { name, .. }) if name == "RtlAddFunctionTable" => {
+                Ok(RTL_ADD_FUNCTION_TABLE)
+            }
+            ("ntdll.dll", ImportName::Name { name, .. }) if name == "RtlDeleteFunctionTable" => {
+                Ok(RTL_DELETE_FUNCTION_TABLE)
+            }
+            _ => Err(Error::ImportNotFound),
+        }
+    }
+
+    #[test]
+    fn registers_exception_tables() {
+        let process = MockProcess::new(1);
+        let syringe = Syringe::new(&process, "http://localhost").import_resolver(resolve_ntdll);
+        let stub = StubExecution::returning(1);
+
+        let mut transaction = Transaction::new(&process, &stub, false);
+        syringe
+            .register_exception_table(&mut transaction, 0x1_0000_0000, (0x4000, 0x30), &stub)
+            .unwrap();
+
+        assert_eq!(
+            stub.calls(),
+            [RemoteCall {
+                function: RTL_ADD_FUNCTION_TABLE,
+                first: 0x1_0000_4000,
+                second: 4,
+                third: 0x1_0000_0000,
+            }]
+        );
+        assert!(matches!(
+            transaction.commit()[..],
+            [Action::ExceptionTable {
+                function_table: 0x1_0000_4000,
+                delete_function: RTL_DELETE_FUNCTION_TABLE,
+            }]
+        ));
+    }
+
+    #[test]
+    fn fails_if_the_exception_table_is_refused() {
+        let process = MockProcess::new(1);
+        let syringe = Syringe::new(&process, "http://localhost").import_resolver(resolve_ntdll);
+        // Only the low byte of a BOOLEAN result is set.
+        let stub = StubExecution::returning(0x1234_5600);
+
+        let mut transaction = Transaction::new(&process, &stub, false);
+        assert!(matches!(
+            syringe.register_exception_table(
+                &mut transaction,
+                0x1_0000_0000,
+                (0x4000, 0x30),
+                &stub
+            ),
+            Err(Error::ExceptionTableFailed)
+        ));
+        assert!(transaction.commit().is_empty());
+    }
+
+    /// An attached module with a registered exception table.
+    fn injected_module(process: &mut MockProcess) -> InjectedModule {
+        process.attach().unwrap();
+        let module_base = process
+            .mem_alloc(None, 0x1000, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE)
+            .unwrap();
+
+        InjectedModule {
+            module_base,
+            actions: vec![
+                Action::Allocation(module_base),
+                Action::ExceptionTable {
+                    function_table: module_base + 0x800,
+                    delete_function: RTL_DELETE_FUNCTION_TABLE,
+                },
+                Action::Attached {
+                    module_base,
+                    entry_point: module_base + 0x10,
+                },
+            ],
+            left_running: false,
+        }
+    }
+
+    #[test]
+    fn ejects_modules() {
+        let mut process = MockProcess::new(1);
+        let mut module = injected_module(&mut process);
+        let stub = StubExecution::returning(1);
+
+        let syringe = Syringe::new(&process, "http://localhost");
+        syringe.eject(&mut module, &stub).unwrap();
+
+        assert!(module.actions.is_empty());
+        assert_eq!(stub.calls().len(), 2);
+        assert!(process.allocations().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_rest_of_a_failed_ejection() {
+        let mut process = MockProcess::new(1);
+        let mut module = injected_module(&mut process);
+        let stub = StubExecution::returning(1);
+
+        process.detach().unwrap();
+        assert!(matches!(
+            Syringe::new(&process, "http://localhost").eject(&mut module, &stub),
+            Err(Error::ProcessError(process::Error::NotAttached))
+        ));
+        assert!(matches!(module.actions[..], [Action::Allocation(_)]));
+
+        process.attach().unwrap();
+        Syringe::new(&process, "http://localhost")
+            .eject(&mut module, &stub)
+            .unwrap();
+
+        // Detached and unregistered once.
+        assert_eq!(stub.calls().len(), 2);
+        assert!(process.allocations().is_empty());
+    }
+
+    #[test]
+    fn leaves_modules_still_running_in_place() {
+        let mut process = MockProcess::new(1);
+        let mut module = injected_module(&mut process);
+        let module_base = module.module_base;
+
+        let syringe = Syringe::new(&process, "http://localhost");
+        assert!(matches!(
+            syringe.eject(&mut module, &StubExecution::still_running()),
+            Err(Error::LibraryLeftRunning(base)) if base == module_base
+        ));
+
+        assert!(matches!(
+            module.actions[..],
+            [Action::Allocation(_), Action::ExceptionTable { .. }]
+        ));
+        assert_eq!(process.allocations().len(), 1);
+
+        // Later attempts neither call into the library nor free it.
+        let stub = StubExecution::returning(1);
+        assert!(matches!(
+            syringe.eject(&mut module, &stub),
+            Err(Error::LibraryLeftRunning(base)) if base == module_base
+        ));
+        assert!(stub.calls().is_empty());
+        assert_eq!(process.allocations().len(), 1);
+    }
+
+    fn kernel32_address(name: &str) -> u64 {
+        0x7FF0_1000_0000 + name.len() as u64 * 0x100
+    }
+
+    fn resolve(dll_name: &str, import: &ImportName) -> Result<u64> {
+        match import {
+            ImportName::Name { name, .. } if dll_name.eq_ignore_ascii_case("kernel32.dll") => {
+                Ok(kernel32_address(name))
+            }
+            _ => resolve_ntdll(dll_name, import),
+        }
+    }
+
+    fn attached_process() -> MockProcess {
+        let mut process = MockProcess::new(1);
+        process.attach().unwrap();
+        process
+    }
+
+    /// The image, which is written to the target in one piece.
+    fn written_image(process: &MockProcess, module_base: u64) -> Vec<u8> {
+        process
+            .writes()
+            .into_iter()
+            .find(|write| write.address == module_base)
+            .unwrap()
+            .data
+    }
+
+    fn assert_mapped(process: &MockProcess, module_base: u64) {
+        let image = written_image(process, module_base);
+        assert_eq!(image.len() as u64, LIBRARY_SIZE);
+
+        // Imports are bound to the resolver's addresses.
+        for name in ["GetCurrentProcessId", "Sleep"] {
+            let address = kernel32_address(name).to_le_bytes();
+            assert!(image.windows(8).any(|bytes| bytes == address), "{name}");
+        }
+
+        // The pointer in .data is relocated to the allocation.
+        let pointers = image[0x3000..0x3010]
+            .chunks_exact(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert!(
+            pointers
+                .iter()
+                .any(|pointer| (module_base..module_base + LIBRARY_SIZE).contains(pointer))
+        );
+        assert!(!pointers.iter().any(|pointer| {
+            (LIBRARY_IMAGE_BASE..LIBRARY_IMAGE_BASE + LIBRARY_SIZE).contains(pointer)
+        }));
+
+        // Headers, .text, .rdata, .data over two pages, and the decommitted .reloc.
+        let protections = (0..LIBRARY_SIZE)
+            .step_by(0x1000)
+            .map(|rva| process.protection(module_base + rva))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            protections,
+            [
+                Some(PAGE_READONLY),
+                Some(PAGE_EXECUTE_READ),
+                Some(PAGE_READONLY),
+                Some(PAGE_READWRITE),
+                Some(PAGE_READWRITE),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        windows,
+        ignore = "reads the Control Flow Guard functions of this kernel32"
+    )]
+    fn injects_libraries_mapped_by_the_service() {
+        let process = attached_process();
+        let stub = StubExecution::returning(1);
+        let syringe = Syringe::new(&process, service_url()).import_resolver(resolve);
+        let init = InitCall {
+            export: "Init".to_string(),
+            payload: b"payload".to_vec(),
+        };
+
+        let report = syringe.inject(LIBRARY, &stub, Some(&init)).unwrap();
+        let module_base = report.module.module_base;
+
+        assert_eq!(report.entry_point_result, Some(1));
+        assert_eq!(report.init_result, Some(1));
+        assert_mapped(&process, module_base);
+
+        let init_rva = syringe.get_export(LIBRARY, "Init").unwrap();
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            calls[0],
+            RemoteCall::dll_main(module_base, module_base + 0x1000, DLL_PROCESS_ATTACH)
+        );
+        assert_eq!(
+            (calls[1].function, calls[1].second, calls[1].third),
+            (module_base + init_rva as u64, 7, module_base)
+        );
+
+        // The payload is only allocated for the call.
+        assert!(
+            process
+                .writes()
+                .iter()
+                .any(|write| write.address == calls[1].first && write.data == b"payload")
+        );
+        assert_eq!(
+            process.allocations(),
+            [(module_base, LIBRARY_SIZE as usize)]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        windows,
+        ignore = "reads the Control Flow Guard functions of this kernel32"
+    )]
+    fn maps_base_independent_images_the_same() {
+        let process = attached_process();
+        let stub = StubExecution::returning(1);
+        let report = Syringe::new(&process, service_url())
+            .import_resolver(resolve)
+            .base_independent(true)
+            .inject(LIBRARY, &stub, None)
+            .unwrap();
+
+        assert_mapped(&process, report.module.module_base);
+
+        let on_service = attached_process();
+        let on_service_report = Syringe::new(&on_service, service_url())
+            .import_resolver(resolve)
+            .inject(LIBRARY, &stub, None)
+            .unwrap();
+
+        // Both processes allocate at the same address.
+        assert_eq!(
+            report.module.module_base,
+            on_service_report.module.module_base
+        );
+        assert_eq!(
+            written_image(&process, report.module.module_base),
+            written_image(&on_service, on_service_report.module.module_base)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        windows,
+        ignore = "reads the Control Flow Guard functions of this kernel32"
+    )]
+    fn rolls_back_libraries_failing_to_attach() {
+        let process = attached_process();
+        let stub = StubExecution::returning(0);
+
+        let result = Syringe::new(&process, service_url())
+            .import_resolver(resolve)
+            .inject(LIBRARY, &stub, None);
+        assert!(matches!(result, Err(Error::EntryPointFailed)));
+
+        let module_base = process.writes()[0].address;
+        assert_eq!(
+            stub.calls(),
+            [
+                RemoteCall::dll_main(module_base, module_base + 0x1000, DLL_PROCESS_ATTACH),
+                RemoteCall::dll_main(module_base, module_base + 0x1000, DLL_PROCESS_DETACH),
+            ]
+        );
+        assert!(process.allocations().is_empty());
+    }
+}
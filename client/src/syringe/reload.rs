@@ -0,0 +1,169 @@
+use crate::{
+    error::{Error, Result},
+    syringe::{ExecutionMethod, InitCall, InjectedModule, Syringe},
+};
+use log::{error, info, warn};
+use std::{thread::sleep, time::Duration};
+
+/// What [`Syringe::hot_reload`] keeps between polls.
+#[derive(Default)]
+struct HotReload {
+    /// Hash of the last build injected, or tried to.
+    last_hash: Option<String>,
+    module: Option<InjectedModule>,
+}
+
+impl Syringe<'_> {
+    fn get_library_hash(&self, library_id: &str) -> Result<String> {
+        self.get_library(library_id)?
+            .sha256
+            .ok_or(Error::MissingLibraryHash)
+    }
+
+    /// Polls the service for changes to the library and replaces the injected instance with every
+    /// new build. Failures are logged and the previous instance is kept. A new build is only
+    /// injected once the previous instance is fully ejected. The loop ends if the service does not
+    /// report library hashes, or with [`Error::LibraryLeftRunning`] if a call into an instance
+    /// times out while running, since that instance can neither be freed nor replaced.
+    pub fn hot_reload(
+        &self,
+        library_id: &str,
+        execution_method: &dyn ExecutionMethod,
+        init: Option<&InitCall>,
+        interval: Duration,
+    ) -> Result<()> {
+        let mut state = HotReload::default();
+
+        loop {
+            self.poll_library(&mut state, library_id, execution_method, init)?;
+            sleep(interval);
+        }
+    }
+
+    /// Replaces the injected instance if the library changed. Only errors that end the hot reload
+    /// are returned.
+    fn poll_library(
+        &self,
+        state: &mut HotReload,
+        library_id: &str,
+        execution_method: &dyn ExecutionMethod,
+        init: Option<&InitCall>,
+    ) -> Result<()> {
+        let hash = match self.get_library_hash(library_id) {
+            Ok(hash) => hash,
+            Err(Error::MissingLibraryHash) => return Err(Error::MissingLibraryHash),
+            Err(err) => {
+                warn!("failed to poll library: {}", err);
+                return Ok(());
+            }
+        };
+
+        if state.last_hash.as_ref() == Some(&hash) {
+            return Ok(());
+        }
+
+        info!("library {} changed (sha256: {})", library_id, hash);
+
+        if let Some(previous) = state.module.as_mut() {
+            info!("ejecting previous instance");
+
+            match self.eject(previous, execution_method) {
+                Ok(()) => state.module = None,
+                Err(err @ Error::LibraryLeftRunning(_)) => return Err(err),
+                // The new build is not injected next to it, the rest of the ejection is retried
+                // on the next poll.
+                Err(err) => {
+                    error!("failed to eject previous instance: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+
+        // Not retried until the library changes again.
+        state.last_hash = Some(hash);
+
+        match self.inject(library_id, execution_method, init) {
+            Ok(report) => {
+                info!("injected library at 0x{:X}", report.module.module_base);
+                state.module = Some(report.module);
+            }
+            Err(err @ Error::LibraryLeftRunning(_)) => return Err(err),
+            Err(err) => error!("failed to inject library: {}", err),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process::{MockProcess, ProcessTrait},
+        syringe::testing::{StubExecution, service_url},
+    };
+    use net_syringe_common::fixups::ImportName;
+
+    const LIBRARY: &str = "lld-link.dll";
+
+    fn attached_process() -> MockProcess {
+        let mut process = MockProcess::new(1);
+        process.attach().unwrap();
+        process
+    }
+
+    fn resolve(_dll_name: &str, _import: &ImportName) -> Result<u64> {
+        Ok(0x7FF0_1000_0000)
+    }
+
+    #[test]
+    #[cfg_attr(
+        windows,
+        ignore = "reads the Control Flow Guard functions of this kernel32"
+    )]
+    fn stops_when_the_previous_instance_is_left_running() {
+        let process = attached_process();
+        let syringe = Syringe::new(&process, service_url()).import_resolver(resolve);
+
+        let module = syringe
+            .inject(LIBRARY, &StubExecution::returning(1), None)
+            .unwrap()
+            .module;
+        let module_base = module.module_base;
+        let mut state = HotReload {
+            last_hash: Some("previous build".to_string()),
+            module: Some(module),
+        };
+
+        let stub = StubExecution::still_running();
+        assert!(matches!(
+            syringe.poll_library(&mut state, LIBRARY, &stub, None),
+            Err(Error::LibraryLeftRunning(base)) if base == module_base
+        ));
+
+        // Only the detach was attempted, nothing was freed or injected next to it.
+        assert_eq!(stub.calls().len(), 1);
+        assert_eq!(state.last_hash.as_deref(), Some("previous build"));
+        assert!(state.module.is_some());
+        assert_eq!(process.allocations().len(), 1);
+        assert_eq!(process.allocations()[0].0, module_base);
+    }
+
+    #[test]
+    #[cfg_attr(
+        windows,
+        ignore = "reads the Control Flow Guard functions of this kernel32"
+    )]
+    fn stops_when_the_new_instance_is_left_running() {
+        let process = attached_process();
+        let syringe = Syringe::new(&process, service_url()).import_resolver(resolve);
+        let stub = StubExecution::still_running();
+
+        assert!(matches!(
+            syringe.hot_reload(LIBRARY, &stub, None, Duration::ZERO),
+            Err(Error::LibraryLeftRunning(_))
+        ));
+        assert_eq!(stub.calls().len(), 1);
+        assert_eq!(process.allocations().len(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+//! Stand-ins for the parts of an injection that need a real target process.
+
+use crate::{
+    error::{Error, Result},
+    process::ProcessTrait,
+    syringe::execution::{EntryResult, ExecutionMethod, RemoteCall},
+};
+use std::{cell::RefCell, sync::OnceLock};
+
+/// Records the calls instead of making them, and answers each with the same result.
+pub struct StubExecution {
+    /// `None` fails every call with [`Error::StillRunning`].
+    result: Option<i32>,
+    calls: RefCell<Vec<RemoteCall>>,
+}
+
+impl StubExecution {
+    pub fn returning(result: i32) -> Self {
+        Self {
+            result: Some(result),
+            calls: RefCell::default(),
+        }
+    }
+
+    /// Every call times out while running.
+    pub fn still_running() -> Self {
+        Self {
+            result: None,
+            calls: RefCell::default(),
+        }
+    }
+
+    pub fn calls(&self) -> Vec<RemoteCall> {
+        self.calls.borrow().clone()
+    }
+}
+
+impl ExecutionMethod for StubExecution {
+    fn call(&self, _process: &dyn ProcessTrait, call: RemoteCall) -> Result<EntryResult> {
+        self.calls.borrow_mut().push(call);
+        self.result.map(EntryResult).ok_or(Error::StillRunning)
+    }
+}
+
+/// Serves the service's fixture libraries on a local port for the rest of the test run and
+/// returns its URL.
+pub fn service_url() -> &'static str {
+    static URL: OnceLock<String> = OnceLock::new();
+
+    URL.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let router = service::router_with_libraries(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../service/fixtures"
+        ));
+        std::thread::spawn(move || {
+            runtime.block_on(async { axum::serve(listener, router).await.unwrap() })
+        });
+
+        url
+    })
+}
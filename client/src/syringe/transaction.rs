@@ -0,0 +1,251 @@
+use crate::{
+    error::{Error, Result},
+    process::{MEM_RELEASE, ProcessTrait},
+    syringe::execution::{DLL_PROCESS_DETACH, ExecutionMethod, RemoteCall},
+};
+use log::{error, info, warn};
+
+/// A side effect of an injection on the target process.
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    /// Memory allocated in the target. Writes and protection changes within it are undone by
+    /// freeing it.
+    Allocation(u64),
+    /// `DllMain` was called with `DLL_PROCESS_ATTACH`.
+    Attached { module_base: u64, entry_point: u64 },
+    /// The exception table at `function_table` was registered, and is unregistered by calling
+    /// `RtlDeleteFunctionTable` at `delete_function`.
+    ExceptionTable {
+        function_table: u64,
+        delete_function: u64,
+    },
+}
+
+/// Records the side effects of an injection and undoes them in reverse order when dropped
+/// without being committed.
+pub struct Transaction<'a> {
+    process: &'a dyn ProcessTrait,
+    execution_method: &'a dyn ExecutionMethod,
+    actions: Vec<Action>,
+    keep_on_failure: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(
+        process: &'a dyn ProcessTrait,
+        execution_method: &'a dyn ExecutionMethod,
+        keep_on_failure: bool,
+    ) -> Self {
+        Self {
+            process,
+            execution_method,
+            actions: Vec::new(),
+            keep_on_failure,
+        }
+    }
+
+    pub fn record(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    /// Keeps the side effects and returns them in the order they were recorded.
+    pub fn commit(mut self) -> Vec<Action> {
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Leaves the side effects in place after a call into the library at `module_base` timed
+    /// out while running, since undoing them could free code that is still executing.
+    pub fn abandon(self, module_base: u64) -> Error {
+        warn!(
+            "call into library at 0x{:X} is still running, leaving it in place: {:?}",
+            module_base, self.actions
+        );
+
+        self.commit();
+
+        Error::LibraryLeftRunning(module_base)
+    }
+}
+
+/// Undoes a side effect of an injection.
+pub fn undo(
+    process: &dyn ProcessTrait,
+    action: &Action,
+    execution_method: &dyn ExecutionMethod,
+) -> Result<()> {
+    match *action {
+        Action::Allocation(address) => {
+            info!("freeing allocation at 0x{:X}", address);
+
+            process.mem_free(address, None, MEM_RELEASE)?;
+        }
+        Action::Attached {
+            module_base,
+            entry_point,
+        } => {
+            info!("detaching library at 0x{:X}", module_base);
+
+            execution_method.execute(process, module_base, entry_point, DLL_PROCESS_DETACH)?;
+        }
+        Action::ExceptionTable {
+            function_table,
+            delete_function,
+        } => {
+            info!("unregistering exception table at 0x{:X}", function_table);
+
+            let result = execution_method.call(
+                process,
+                RemoteCall {
+                    function: delete_function,
+                    first: function_table,
+                    second: 0,
+                    third: 0,
+                },
+            )?;
+
+            if !result.succeeded_boolean() {
+                warn!(
+                    "exception table at 0x{:X} was not registered",
+                    function_table
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.actions.is_empty() {
+            return;
+        }
+
+        if self.keep_on_failure {
+            warn!(
+                "injection failed, keeping state for debugging: {:?}",
+                self.actions
+            );
+            return;
+        }
+
+        info!("injection failed, rolling back");
+
+        for action in self.actions.iter().rev() {
+            match undo(self.process, action, self.execution_method) {
+                Ok(()) => {}
+                // A call is still running in the library, freeing it could crash the target.
+                Err(Error::StillRunning) => {
+                    error!(
+                        "undoing {:?} timed out while running, stopping rollback",
+                        action
+                    );
+                    break;
+                }
+                Err(err) => error!("failed to undo {:?}: {}", action, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process::{MEM_COMMIT, MEM_RESERVE, MockProcess, PAGE_READWRITE},
+        syringe::testing::StubExecution,
+    };
+
+    fn process() -> MockProcess {
+        let mut process = MockProcess::new(1);
+        process.attach().unwrap();
+        process
+    }
+
+    fn attach(transaction: &mut Transaction, process: &MockProcess) -> u64 {
+        let module_base = process
+            .mem_alloc(None, 0x1000, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE)
+            .unwrap();
+
+        transaction.record(Action::Allocation(module_base));
+        transaction.record(Action::ExceptionTable {
+            function_table: module_base + 0x800,
+            delete_function: 0x7FF0_0000_2000,
+        });
+        transaction.record(Action::Attached {
+            module_base,
+            entry_point: module_base + 0x10,
+        });
+
+        module_base
+    }
+
+    #[test]
+    fn rolls_back_in_reverse_order() {
+        let process = process();
+        let stub = StubExecution::returning(1);
+
+        let module_base = {
+            let mut transaction = Transaction::new(&process, &stub, false);
+            attach(&mut transaction, &process)
+        };
+
+        assert_eq!(
+            stub.calls(),
+            [
+                RemoteCall::dll_main(module_base, module_base + 0x10, DLL_PROCESS_DETACH),
+                RemoteCall {
+                    function: 0x7FF0_0000_2000,
+                    first: module_base + 0x800,
+                    second: 0,
+                    third: 0,
+                },
+            ]
+        );
+        assert!(process.allocations().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_image_while_detach_is_running() {
+        let process = process();
+        let stub = StubExecution::still_running();
+
+        let module_base = {
+            let mut transaction = Transaction::new(&process, &stub, false);
+            attach(&mut transaction, &process)
+        };
+
+        assert_eq!(stub.calls().len(), 1);
+        assert_eq!(process.allocations(), [(module_base, 0x1000)]);
+    }
+
+    #[test]
+    fn keeps_everything_on_failure_if_asked() {
+        let process = process();
+        let stub = StubExecution::returning(1);
+
+        let module_base = {
+            let mut transaction = Transaction::new(&process, &stub, true);
+            attach(&mut transaction, &process)
+        };
+
+        assert!(stub.calls().is_empty());
+        assert_eq!(process.allocations(), [(module_base, 0x1000)]);
+    }
+
+    #[test]
+    fn abandons_everything() {
+        let process = process();
+        let stub = StubExecution::returning(1);
+
+        let mut transaction = Transaction::new(&process, &stub, false);
+        let module_base = attach(&mut transaction, &process);
+
+        assert!(matches!(
+            transaction.abandon(module_base),
+            Error::LibraryLeftRunning(base) if base == module_base
+        ));
+        assert!(stub.calls().is_empty());
+        assert_eq!(process.allocations(), [(module_base, 0x1000)]);
+    }
+}
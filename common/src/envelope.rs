@@ -0,0 +1,718 @@
+//! Compact binary envelope for mapped images, selected with `Accept: application/vnd.net-syringe.image`.
+//!
+//! All integers are little-endian:
+//!
+//! ```text
+//! magic            [u8; 4] = "NSIM"
+//! version          u16
+//! flags            u16
+//! size_of_image    u32
+//! entry_point      u32 (0 if the image has no entry point)
+//! region_count     u32, then { rva: u32, size: u32, characteristics: u32 } per region
+//! tls_count        u32, then { rva: u32 } per TLS callback
+//! exception_rva    u32 (0 if the image has no exception table)
+//! exception_size   u32
+//! chunk_count      u32, then { rva: u32, size: u32, encoding: u8, length: u32, data: [u8; length] }
+//! ```
+//!
+//! Pages that are entirely zero are not stored, the decoder starts from a zeroed image.
+//!
+//! With [`FLAG_FIXUPS`] set, a fixup table for a base-independent image follows the chunks:
+//!
+//! ```text
+//! image_base       u64
+//! reloc_count      u32, then { rva: u32, kind: u8, param: u16 (IMAGE_REL_BASED_HIGHADJ only) } per relocation
+//! import_count     u32, then { thunk_rva: u32, module: str, kind: u8, name: { hint: u16, name: str } | ordinal: u16 }
+//! ```
+//!
+//! With [`FLAG_DISCARDABLE`] set, the image's discardable sections follow last:
+//!
+//! ```text
+//! discardable_count u32, then { name: str, rva: u32, size: u32 } per section
+//! ```
+//!
+//! where `str` is a `u16` length followed by that many UTF-8 bytes.
+
+use crate::{
+    error::{Error, Result},
+    fixups::{FixupTable, IMAGE_REL_BASED_HIGHADJ, ImportFixup, ImportName, Relocation},
+};
+
+pub const CONTENT_TYPE: &str = "application/vnd.net-syringe.image";
+pub const MAGIC: &[u8; 4] = b"NSIM";
+pub const VERSION: u16 = 1;
+
+/// A fixup table follows the image chunks.
+pub const FLAG_FIXUPS: u16 = 1 << 0;
+/// The image has no relocations and must be placed at the fixup table's image base.
+pub const FLAG_RELOCS_STRIPPED: u16 = 1 << 1;
+
+/// A list of discardable sections follows everything else.
+pub const FLAG_DISCARDABLE: u16 = 1 << 2;
+
+const PAGE_SIZE: usize = 0x1000;
+
+const ENCODING_RAW: u8 = 0;
+const ENCODING_PACK_BITS: u8 = 1;
+
+const IMPORT_BY_NAME: u8 = 0;
+const IMPORT_BY_ORDINAL: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub rva: u32,
+    pub size: u32,
+    pub characteristics: u32,
+}
+
+/// A section marked `IMAGE_SCN_MEM_DISCARDABLE`, not needed once the image is initialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscardableSection {
+    pub name: String,
+    pub rva: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub entry_point: Option<u32>,
+    pub regions: Vec<Region>,
+    pub tls_callbacks: Vec<u32>,
+    pub exception_table: Option<(u32, u32)>,
+    /// Relocations and imports left for the client to apply, for base-independent images.
+    pub fixups: Option<FixupTable>,
+    pub discardable: Vec<DiscardableSection>,
+    pub image: Vec<u8>,
+}
+
+pub fn encode(envelope: &Envelope) -> Vec<u8> {
+    let mut writer = Writer::default();
+
+    let mut flags = match &envelope.fixups {
+        Some(fixups) if fixups.relocs_stripped => FLAG_FIXUPS | FLAG_RELOCS_STRIPPED,
+        Some(_) => FLAG_FIXUPS,
+        None => 0,
+    };
+    if !envelope.discardable.is_empty() {
+        flags |= FLAG_DISCARDABLE;
+    }
+
+    writer.bytes(MAGIC);
+    writer.u16(VERSION);
+    writer.u16(flags);
+    writer.u32(envelope.image.len() as u32);
+    writer.u32(envelope.entry_point.unwrap_or(0));
+
+    writer.u32(envelope.regions.len() as u32);
+    for region in &envelope.regions {
+        writer.u32(region.rva);
+        writer.u32(region.size);
+        writer.u32(region.characteristics);
+    }
+
+    writer.u32(envelope.tls_callbacks.len() as u32);
+    for rva in &envelope.tls_callbacks {
+        writer.u32(*rva);
+    }
+
+    let (exception_rva, exception_size) = envelope.exception_table.unwrap_or((0, 0));
+    writer.u32(exception_rva);
+    writer.u32(exception_size);
+
+    let chunks = non_zero_runs(&envelope.image);
+
+    writer.u32(chunks.len() as u32);
+    for (offset, data) in chunks {
+        let packed = pack_bits(data);
+        let (encoding, payload) = if packed.len() < data.len() {
+            (ENCODING_PACK_BITS, packed.as_slice())
+        } else {
+            (ENCODING_RAW, data)
+        };
+
+        writer.u32(offset as u32);
+        writer.u32(data.len() as u32);
+        writer.u8(encoding);
+        writer.u32(payload.len() as u32);
+        writer.bytes(payload);
+    }
+
+    if let Some(fixups) = &envelope.fixups {
+        writer.u64(fixups.image_base);
+
+        writer.u32(fixups.relocations.len() as u32);
+        for reloc in &fixups.relocations {
+            writer.u32(reloc.rva);
+            writer.u8(reloc.kind);
+            if reloc.kind == IMAGE_REL_BASED_HIGHADJ {
+                writer.u16(reloc.param);
+            }
+        }
+
+        writer.u32(fixups.imports.len() as u32);
+        for import in &fixups.imports {
+            writer.u32(import.thunk_rva);
+            writer.str(&import.module);
+            match &import.import {
+                ImportName::Name { hint, name } => {
+                    writer.u8(IMPORT_BY_NAME);
+                    writer.u16(*hint);
+                    writer.str(name);
+                }
+                ImportName::Ordinal(ordinal) => {
+                    writer.u8(IMPORT_BY_ORDINAL);
+                    writer.u16(*ordinal);
+                }
+            }
+        }
+    }
+
+    if !envelope.discardable.is_empty() {
+        writer.u32(envelope.discardable.len() as u32);
+        for section in &envelope.discardable {
+            writer.str(&section.name);
+            writer.u32(section.rva);
+            writer.u32(section.size);
+        }
+    }
+
+    writer.0
+}
+
+pub fn decode(data: &[u8]) -> Result<Envelope> {
+    let mut reader = Reader(data);
+
+    if reader.bytes(4)? != MAGIC || reader.u16()? != VERSION {
+        return Err(Error::InvalidEnvelope);
+    }
+    let flags = reader.u16()?;
+
+    let size_of_image = reader.u32()? as usize;
+    let entry_point = match reader.u32()? {
+        0 => None,
+        rva => Some(rva),
+    };
+
+    let region_count = reader.u32()?;
+    let mut regions = Vec::new();
+    for _ in 0..region_count {
+        regions.push(Region {
+            rva: reader.u32()?,
+            size: reader.u32()?,
+            characteristics: reader.u32()?,
+        });
+    }
+
+    let tls_count = reader.u32()?;
+    let mut tls_callbacks = Vec::new();
+    for _ in 0..tls_count {
+        tls_callbacks.push(reader.u32()?);
+    }
+
+    let exception_table = match (reader.u32()?, reader.u32()?) {
+        (0, _) | (_, 0) => None,
+        table => Some(table),
+    };
+
+    let mut image = vec![0u8; size_of_image];
+
+    let chunk_count = reader.u32()?;
+    for _ in 0..chunk_count {
+        let rva = reader.u32()? as usize;
+        let size = reader.u32()? as usize;
+        let encoding = reader.u8()?;
+        let length = reader.u32()? as usize;
+        let payload = reader.bytes(length)?;
+
+        let dst = image
+            .get_mut(rva..rva.checked_add(size).ok_or(Error::InvalidEnvelope)?)
+            .ok_or(Error::InvalidEnvelope)?;
+
+        match encoding {
+            ENCODING_RAW if payload.len() == size => dst.copy_from_slice(payload),
+            ENCODING_PACK_BITS => unpack_bits(payload, dst)?,
+            _ => return Err(Error::InvalidEnvelope),
+        }
+    }
+
+    let fixups = if flags & FLAG_FIXUPS != 0 {
+        let image_base = reader.u64()?;
+
+        let reloc_count = reader.u32()?;
+        let mut relocations = Vec::new();
+        for _ in 0..reloc_count {
+            let rva = reader.u32()?;
+            let kind = reader.u8()?;
+            let param = if kind == IMAGE_REL_BASED_HIGHADJ {
+                reader.u16()?
+            } else {
+                0
+            };
+
+            relocations.push(Relocation { rva, kind, param });
+        }
+
+        let import_count = reader.u32()?;
+        let mut imports = Vec::new();
+        for _ in 0..import_count {
+            let thunk_rva = reader.u32()?;
+            let module = reader.str()?;
+            let import = match reader.u8()? {
+                IMPORT_BY_NAME => ImportName::Name {
+                    hint: reader.u16()?,
+                    name: reader.str()?,
+                },
+                IMPORT_BY_ORDINAL => ImportName::Ordinal(reader.u16()?),
+                _ => return Err(Error::InvalidEnvelope),
+            };
+
+            imports.push(ImportFixup {
+                thunk_rva,
+                module,
+                import,
+            });
+        }
+
+        Some(FixupTable {
+            image_base,
+            relocs_stripped: flags & FLAG_RELOCS_STRIPPED != 0,
+            relocations,
+            imports,
+        })
+    } else {
+        None
+    };
+
+    let mut discardable = Vec::new();
+    if flags & FLAG_DISCARDABLE != 0 {
+        let discardable_count = reader.u32()?;
+        for _ in 0..discardable_count {
+            discardable.push(DiscardableSection {
+                name: reader.str()?,
+                rva: reader.u32()?,
+                size: reader.u32()?,
+            });
+        }
+    }
+
+    Ok(Envelope {
+        entry_point,
+        regions,
+        tls_callbacks,
+        exception_table,
+        fixups,
+        discardable,
+        image,
+    })
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, value: &[u8]) {
+        self.0.extend_from_slice(value);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes(value.as_bytes());
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::InvalidEnvelope);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| Error::InvalidEnvelope)
+    }
+}
+
+/// Splits the image into runs of consecutive pages that contain at least one non-zero byte.
+fn non_zero_runs(data: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut runs = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (idx, page) in data.chunks(PAGE_SIZE).enumerate() {
+        let offset = idx * PAGE_SIZE;
+        let is_zero = page.iter().all(|b| *b == 0);
+
+        match (start, is_zero) {
+            (None, false) => start = Some(offset),
+            (Some(run_start), true) => {
+                runs.push((run_start, &data[run_start..offset]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(run_start) = start {
+        runs.push((run_start, &data[run_start..]));
+    }
+
+    runs
+}
+
+/// PackBits-style run-length encoding: a control byte `n < 128` is followed by `n + 1` literal
+/// bytes, a control byte `n >= 128` is followed by one byte repeated `n - 125` times.
+fn pack_bits(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(130)
+            .take_while(|b| **b == data[i])
+            .count();
+
+        if run >= 3 {
+            out.push((run + 125) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < data.len() && i - start < 128 {
+            if i + 2 < data.len() && data[i] == data[i + 1] && data[i] == data[i + 2] {
+                break;
+            }
+            i += 1;
+        }
+
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+
+    out
+}
+
+/// Reverses [`pack_bits`] into `dst`, which must be filled exactly.
+fn unpack_bits(mut src: &[u8], dst: &mut [u8]) -> Result<()> {
+    let mut pos = 0;
+
+    while let Some((&control, rest)) = src.split_first() {
+        let (len, remaining) = if control < 128 {
+            let len = control as usize + 1;
+            let literal = rest.get(..len).ok_or(Error::InvalidEnvelope)?;
+            dst.get_mut(pos..pos + len)
+                .ok_or(Error::InvalidEnvelope)?
+                .copy_from_slice(literal);
+            (len, &rest[len..])
+        } else {
+            let len = control as usize - 125;
+            let value = *rest.first().ok_or(Error::InvalidEnvelope)?;
+            dst.get_mut(pos..pos + len)
+                .ok_or(Error::InvalidEnvelope)?
+                .fill(value);
+            (len, &rest[1..])
+        };
+
+        pos += len;
+        src = remaining;
+    }
+
+    if pos != dst.len() {
+        return Err(Error::InvalidEnvelope);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Vec<u8> {
+        // A header page, a zero page that is not stored, then a page with runs and literals and
+        // a partial page at the end.
+        let mut image = vec![0u8; 3 * PAGE_SIZE + 0x10];
+        image[..2].copy_from_slice(b"MZ");
+        image[2 * PAGE_SIZE..2 * PAGE_SIZE + 0x200].fill(0xCC);
+        image[2 * PAGE_SIZE + 0x200..2 * PAGE_SIZE + 0x210].copy_from_slice(b"0123456789abcdef");
+        image[3 * PAGE_SIZE + 0xF] = 0xC3;
+        image
+    }
+
+    fn envelope() -> Envelope {
+        Envelope {
+            entry_point: Some(0x2000),
+            regions: vec![
+                Region {
+                    rva: 0,
+                    size: 0x1000,
+                    characteristics: 0x4000_0040,
+                },
+                Region {
+                    rva: 0x2000,
+                    size: 0x1010,
+                    characteristics: 0x6000_0020,
+                },
+            ],
+            tls_callbacks: vec![0x2010, 0x2020],
+            exception_table: Some((0x3000, 0x18)),
+            fixups: None,
+            discardable: Vec::new(),
+            image: image(),
+        }
+    }
+
+    fn fixups() -> FixupTable {
+        FixupTable {
+            image_base: 0x1_8000_0000,
+            relocs_stripped: false,
+            relocations: vec![
+                Relocation {
+                    rva: 0x2008,
+                    kind: crate::fixups::IMAGE_REL_BASED_DIR64,
+                    param: 0,
+                },
+                Relocation {
+                    rva: 0x2100,
+                    kind: IMAGE_REL_BASED_HIGHADJ,
+                    param: 0xFFF0,
+                },
+            ],
+            imports: vec![
+                ImportFixup {
+                    thunk_rva: 0x3000,
+                    module: "KERNEL32.dll".to_string(),
+                    import: ImportName::Name {
+                        hint: 0x2A,
+                        name: "Sleep".to_string(),
+                    },
+                },
+                ImportFixup {
+                    thunk_rva: 0x3008,
+                    module: "ws2_32.dll".to_string(),
+                    import: ImportName::Ordinal(23),
+                },
+            ],
+        }
+    }
+
+    fn discardable() -> Vec<DiscardableSection> {
+        vec![DiscardableSection {
+            name: ".reloc".to_string(),
+            rva: 0x4000,
+            size: 0x10,
+        }]
+    }
+
+    #[test]
+    fn round_trips() {
+        let envelope = envelope();
+        let encoded = encode(&envelope);
+
+        assert_eq!(&encoded[..4], MAGIC);
+        assert_eq!(u16::from_le_bytes([encoded[6], encoded[7]]), 0);
+        assert_eq!(decode(&encoded).unwrap(), envelope);
+    }
+
+    #[test]
+    fn round_trips_without_optional_fields() {
+        let envelope = Envelope {
+            entry_point: None,
+            regions: Vec::new(),
+            tls_callbacks: Vec::new(),
+            exception_table: None,
+            fixups: None,
+            discardable: Vec::new(),
+            image: vec![0; PAGE_SIZE],
+        };
+
+        assert_eq!(decode(&encode(&envelope)).unwrap(), envelope);
+    }
+
+    #[test]
+    fn round_trips_with_fixups() {
+        let envelope = Envelope {
+            fixups: Some(fixups()),
+            ..envelope()
+        };
+        let encoded = encode(&envelope);
+
+        assert_eq!(u16::from_le_bytes([encoded[6], encoded[7]]), FLAG_FIXUPS);
+        assert_eq!(decode(&encoded).unwrap(), envelope);
+
+        let envelope = Envelope {
+            fixups: Some(FixupTable {
+                relocs_stripped: true,
+                relocations: Vec::new(),
+                ..fixups()
+            }),
+            ..envelope
+        };
+        let encoded = encode(&envelope);
+
+        assert_eq!(
+            u16::from_le_bytes([encoded[6], encoded[7]]),
+            FLAG_FIXUPS | FLAG_RELOCS_STRIPPED
+        );
+        assert_eq!(decode(&encoded).unwrap(), envelope);
+    }
+
+    #[test]
+    fn round_trips_with_discardable_sections() {
+        let envelope = Envelope {
+            discardable: discardable(),
+            ..envelope()
+        };
+        let encoded = encode(&envelope);
+
+        assert_eq!(
+            u16::from_le_bytes([encoded[6], encoded[7]]),
+            FLAG_DISCARDABLE
+        );
+        assert_eq!(decode(&encoded).unwrap(), envelope);
+
+        let envelope = Envelope {
+            fixups: Some(fixups()),
+            ..envelope
+        };
+        assert_eq!(decode(&encode(&envelope)).unwrap(), envelope);
+    }
+
+    #[test]
+    fn skips_zero_pages() {
+        let envelope = envelope();
+        let encoded = encode(&envelope);
+
+        // The run of 0x200 bytes packs down, and the zero page is not stored at all.
+        assert!(encoded.len() < 2 * PAGE_SIZE);
+        assert_eq!(non_zero_runs(&envelope.image).len(), 2);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let envelope = Envelope {
+            fixups: Some(fixups()),
+            discardable: discardable(),
+            ..envelope()
+        };
+        let encoded = encode(&envelope);
+
+        for len in 0..encoded.len() {
+            assert!(
+                matches!(decode(&encoded[..len]), Err(Error::InvalidEnvelope)),
+                "decoded {len} of {} bytes",
+                encoded.len()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_magic_and_version() {
+        let mut encoded = encode(&envelope());
+        encoded[0] = b'X';
+        assert!(matches!(decode(&encoded), Err(Error::InvalidEnvelope)));
+
+        let mut encoded = encode(&envelope());
+        encoded[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(decode(&encoded), Err(Error::InvalidEnvelope)));
+    }
+
+    #[test]
+    fn rejects_chunks_outside_the_image() {
+        let mut encoded = encode(&envelope());
+
+        // size_of_image follows the magic, version and flags.
+        encoded[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        assert!(matches!(decode(&encoded), Err(Error::InvalidEnvelope)));
+    }
+
+    fn round_trip_bits(data: &[u8]) -> Vec<u8> {
+        let packed = pack_bits(data);
+        let mut unpacked = vec![0; data.len()];
+        unpack_bits(&packed, &mut unpacked).unwrap();
+        assert_eq!(unpacked, data);
+        packed
+    }
+
+    #[test]
+    fn packs_runs_of_up_to_130_bytes() {
+        assert_eq!(round_trip_bits(&[7; 3]), [128, 7]);
+        assert_eq!(round_trip_bits(&[7; 130]), [255, 7]);
+        assert_eq!(round_trip_bits(&[7; 131]), [255, 7, 0, 7]);
+        assert_eq!(round_trip_bits(&[7; 133]), [255, 7, 128, 7]);
+    }
+
+    #[test]
+    fn packs_literals_of_up_to_128_bytes() {
+        let literal: Vec<u8> = (0..=255).collect();
+
+        let packed = round_trip_bits(&literal[..128]);
+        assert_eq!(packed[0], 127);
+        assert_eq!(packed.len(), 129);
+
+        let packed = round_trip_bits(&literal[..129]);
+        assert_eq!(packed[0], 127);
+        assert_eq!(packed[129], 0);
+        assert_eq!(packed.len(), 131);
+    }
+
+    #[test]
+    fn packs_pairs_as_literals() {
+        assert_eq!(round_trip_bits(&[1, 1, 2, 2]), [3, 1, 1, 2, 2]);
+        assert_eq!(round_trip_bits(&[1, 2, 2, 2, 3]), [0, 1, 128, 2, 0, 3]);
+        assert!(round_trip_bits(&[]).is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_bits() {
+        let mut dst = [0; 4];
+
+        // Literal and run missing their data.
+        assert!(unpack_bits(&[3, 1, 2], &mut dst).is_err());
+        assert!(unpack_bits(&[129], &mut dst).is_err());
+        // Overflowing and underfilling the destination.
+        assert!(unpack_bits(&[130, 0], &mut dst).is_err());
+        assert!(unpack_bits(&[128, 0], &mut dst).is_err());
+        assert!(unpack_bits(&[129, 5], &mut dst).is_ok());
+        assert_eq!(dst, [5; 4]);
+    }
+}
@@ -0,0 +1,15 @@
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid image envelope")]
+    InvalidEnvelope,
+    #[error("out of range")]
+    OutOfRange,
+    #[error("unsupported relocation type {kind} at 0x{rva:X}")]
+    UnsupportedRelocation { kind: u8, rva: u32 },
+    #[error("image has no relocations and must be mapped at its preferred base 0x{0:X}")]
+    RelocationsStripped(u64),
+    #[error("missing import {import} from {module}")]
+    MissingImport { module: String, import: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,435 @@
+//! Relocation and import fixups for images the service maps without knowing the allocation base.
+
+use crate::error::{Error, Result};
+use std::fmt;
+
+pub const IMAGE_REL_BASED_ABSOLUTE: u8 = 0;
+pub const IMAGE_REL_BASED_HIGH: u8 = 1;
+pub const IMAGE_REL_BASED_LOW: u8 = 2;
+pub const IMAGE_REL_BASED_HIGHLOW: u8 = 3;
+pub const IMAGE_REL_BASED_HIGHADJ: u8 = 4;
+pub const IMAGE_REL_BASED_ARM_MOV32: u8 = 5;
+pub const IMAGE_REL_BASED_THUMB_MOV32: u8 = 7;
+pub const IMAGE_REL_BASED_DIR64: u8 = 10;
+
+/// Relocation types [`apply_relocation`] knows how to apply.
+pub const SUPPORTED_RELOCATIONS: &[u8] = &[
+    IMAGE_REL_BASED_ABSOLUTE,
+    IMAGE_REL_BASED_HIGH,
+    IMAGE_REL_BASED_LOW,
+    IMAGE_REL_BASED_HIGHLOW,
+    IMAGE_REL_BASED_HIGHADJ,
+    IMAGE_REL_BASED_ARM_MOV32,
+    IMAGE_REL_BASED_THUMB_MOV32,
+    IMAGE_REL_BASED_DIR64,
+];
+
+/// Key for comparing module names, which Windows treats case-insensitively.
+pub fn normalize_module_name(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportName {
+    Name { hint: u16, name: String },
+    Ordinal(u16),
+}
+
+impl fmt::Display for ImportName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportName::Name { name, .. } => write!(f, "{}", name),
+            ImportName::Ordinal(ordinal) => write!(f, "#{}", ordinal),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub rva: u32,
+    pub kind: u8,
+    /// Low 16 bits of the target for `IMAGE_REL_BASED_HIGHADJ`, taken from the following entry.
+    pub param: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportFixup {
+    /// RVA of the import address table slot to fill in.
+    pub thunk_rva: u32,
+    pub module: String,
+    pub import: ImportName,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FixupTable {
+    /// Preferred base the image was mapped for.
+    pub image_base: u64,
+    /// The image has no relocations and can only be placed at `image_base`.
+    pub relocs_stripped: bool,
+    pub relocations: Vec<Relocation>,
+    pub imports: Vec<ImportFixup>,
+}
+
+impl FixupTable {
+    pub fn apply_relocations(&self, image: &mut [u8], allocation_base: u64) -> Result<()> {
+        let delta = allocation_base.wrapping_sub(self.image_base);
+
+        if self.relocs_stripped && delta != 0 {
+            return Err(Error::RelocationsStripped(self.image_base));
+        }
+
+        for reloc in &self.relocations {
+            apply_relocation(image, reloc, delta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fills in every import address table slot with the address returned by `resolve`.
+    pub fn apply_imports<F>(&self, image: &mut [u8], mut resolve: F) -> Result<()>
+    where
+        F: FnMut(&ImportFixup) -> Option<u64>,
+    {
+        for import in &self.imports {
+            let address = resolve(import).ok_or_else(|| Error::MissingImport {
+                module: import.module.clone(),
+                import: import.import.to_string(),
+            })?;
+
+            let slot: &mut [u8; 8] = slot_mut(image, import.thunk_rva as usize)?;
+            *slot = address.to_le_bytes();
+        }
+
+        Ok(())
+    }
+}
+
+/// Applies a single base relocation for an image moved by `delta` from its preferred base.
+pub fn apply_relocation(image: &mut [u8], reloc: &Relocation, delta: u64) -> Result<()> {
+    let rva = reloc.rva as usize;
+    let delta32 = delta as u32;
+
+    match reloc.kind {
+        IMAGE_REL_BASED_ABSOLUTE => {}
+        IMAGE_REL_BASED_HIGH => {
+            let slot: &mut [u8; 2] = slot_mut(image, rva)?;
+            let value = ((u16::from_le_bytes(*slot) as u32) << 16).wrapping_add(delta32);
+            *slot = ((value >> 16) as u16).to_le_bytes();
+        }
+        IMAGE_REL_BASED_LOW => {
+            let slot: &mut [u8; 2] = slot_mut(image, rva)?;
+            *slot = u16::from_le_bytes(*slot)
+                .wrapping_add(delta32 as u16)
+                .to_le_bytes();
+        }
+        IMAGE_REL_BASED_HIGHLOW => {
+            let slot: &mut [u8; 4] = slot_mut(image, rva)?;
+            *slot = u32::from_le_bytes(*slot)
+                .wrapping_add(delta32)
+                .to_le_bytes();
+        }
+        IMAGE_REL_BASED_HIGHADJ => {
+            let slot: &mut [u8; 2] = slot_mut(image, rva)?;
+            let value = ((u16::from_le_bytes(*slot) as u32) << 16)
+                .wrapping_add(reloc.param as i16 as i32 as u32)
+                .wrapping_add(delta32)
+                .wrapping_add(0x8000);
+            *slot = ((value >> 16) as u16).to_le_bytes();
+        }
+        IMAGE_REL_BASED_ARM_MOV32 => {
+            // MOVW followed by MOVT, each holding 16 bits of the address as imm4:imm12.
+            let slot: &mut [u8; 8] = slot_mut(image, rva)?;
+            let movw = u32::from_le_bytes(slot[0..4].try_into().unwrap());
+            let movt = u32::from_le_bytes(slot[4..8].try_into().unwrap());
+
+            let value =
+                ((arm_mov_imm(movt) as u32) << 16 | arm_mov_imm(movw) as u32).wrapping_add(delta32);
+
+            slot[0..4].copy_from_slice(&arm_mov_with_imm(movw, value as u16).to_le_bytes());
+            slot[4..8].copy_from_slice(&arm_mov_with_imm(movt, (value >> 16) as u16).to_le_bytes());
+        }
+        IMAGE_REL_BASED_THUMB_MOV32 => {
+            // Thumb-2 MOVW followed by MOVT, each holding 16 bits of the address as imm4:i:imm3:imm8.
+            let slot: &mut [u8; 8] = slot_mut(image, rva)?;
+            let halfwords: [u16; 4] =
+                std::array::from_fn(|idx| u16::from_le_bytes([slot[idx * 2], slot[idx * 2 + 1]]));
+
+            let value = ((thumb_mov_imm(halfwords[2], halfwords[3]) as u32) << 16
+                | thumb_mov_imm(halfwords[0], halfwords[1]) as u32)
+                .wrapping_add(delta32);
+
+            let (movw_lo, movw_hi) = thumb_mov_with_imm(halfwords[0], halfwords[1], value as u16);
+            let (movt_lo, movt_hi) =
+                thumb_mov_with_imm(halfwords[2], halfwords[3], (value >> 16) as u16);
+
+            for (idx, halfword) in [movw_lo, movw_hi, movt_lo, movt_hi].iter().enumerate() {
+                slot[idx * 2..idx * 2 + 2].copy_from_slice(&halfword.to_le_bytes());
+            }
+        }
+        IMAGE_REL_BASED_DIR64 => {
+            let slot: &mut [u8; 8] = slot_mut(image, rva)?;
+            *slot = u64::from_le_bytes(*slot).wrapping_add(delta).to_le_bytes();
+        }
+        kind => {
+            return Err(Error::UnsupportedRelocation {
+                kind,
+                rva: reloc.rva,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn arm_mov_imm(insn: u32) -> u16 {
+    (((insn >> 16) & 0xF) << 12 | (insn & 0xFFF)) as u16
+}
+
+fn arm_mov_with_imm(insn: u32, imm: u16) -> u32 {
+    let imm = imm as u32;
+    (insn & !0x000F_0FFF) | ((imm >> 12) << 16) | (imm & 0xFFF)
+}
+
+fn thumb_mov_imm(lo: u16, hi: u16) -> u16 {
+    (lo & 0xF) << 12 | ((lo >> 10) & 1) << 11 | ((hi >> 12) & 0x7) << 8 | (hi & 0xFF)
+}
+
+fn thumb_mov_with_imm(lo: u16, hi: u16, imm: u16) -> (u16, u16) {
+    (
+        (lo & !0x040F) | (imm >> 12) | ((imm >> 11) & 1) << 10,
+        (hi & !0x70FF) | ((imm >> 8) & 0x7) << 12 | (imm & 0xFF),
+    )
+}
+
+fn slot_mut<const N: usize>(image: &mut [u8], rva: usize) -> Result<&mut [u8; N]> {
+    image
+        .get_mut(rva..rva.checked_add(N).ok_or(Error::OutOfRange)?)
+        .ok_or(Error::OutOfRange)?
+        .try_into()
+        .map_err(|_| Error::OutOfRange)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_BASE: u64 = 0x1_8000_0000;
+
+    fn reloc(rva: u32, kind: u8) -> Relocation {
+        Relocation {
+            rva,
+            kind,
+            param: 0,
+        }
+    }
+
+    fn table(relocations: Vec<Relocation>) -> FixupTable {
+        FixupTable {
+            image_base: IMAGE_BASE,
+            relocs_stripped: false,
+            relocations,
+            imports: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_dir64_and_highlow() {
+        let mut image = vec![0u8; 0x20];
+        image[0x8..0x10].copy_from_slice(&(IMAGE_BASE + 0x1234).to_le_bytes());
+        image[0x10..0x14].copy_from_slice(&0x1000_1234u32.to_le_bytes());
+
+        let table = table(vec![
+            reloc(0, IMAGE_REL_BASED_ABSOLUTE),
+            reloc(0x8, IMAGE_REL_BASED_DIR64),
+            reloc(0x10, IMAGE_REL_BASED_HIGHLOW),
+        ]);
+        table
+            .apply_relocations(&mut image, 0x7FF6_0000_0000)
+            .unwrap();
+
+        assert_eq!(image[..8], [0; 8]);
+        assert_eq!(
+            u64::from_le_bytes(image[0x8..0x10].try_into().unwrap()),
+            0x7FF6_0000_1234
+        );
+        // Only the low 32 bits of the delta apply.
+        assert_eq!(
+            u32::from_le_bytes(image[0x10..0x14].try_into().unwrap()),
+            0x1000_1234u32.wrapping_sub(0x8000_0000)
+        );
+    }
+
+    #[test]
+    fn applies_moves_below_the_image_base() {
+        let mut image = (IMAGE_BASE + 0x1234).to_le_bytes().to_vec();
+
+        table(vec![reloc(0, IMAGE_REL_BASED_DIR64)])
+            .apply_relocations(&mut image, 0x1000_0000)
+            .unwrap();
+
+        assert_eq!(u64::from_le_bytes(image.try_into().unwrap()), 0x1000_1234);
+    }
+
+    #[test]
+    fn applies_high_low_and_highadj() {
+        let mut image = [0u8; 6];
+        image[0..2].copy_from_slice(&0x0001u16.to_le_bytes());
+        image[2..4].copy_from_slice(&0xFFF0u16.to_le_bytes());
+        // 0x0001_FFF0 split as HIGHADJ: 0x0002 << 16 plus the sign-extended low half 0xFFF0.
+        image[4..6].copy_from_slice(&0x0002u16.to_le_bytes());
+
+        let relocations = [
+            reloc(0, IMAGE_REL_BASED_HIGH),
+            reloc(2, IMAGE_REL_BASED_LOW),
+            Relocation {
+                rva: 4,
+                kind: IMAGE_REL_BASED_HIGHADJ,
+                param: 0xFFF0,
+            },
+        ];
+        for reloc in &relocations {
+            apply_relocation(&mut image, reloc, 0x8010).unwrap();
+        }
+
+        assert_eq!(u16::from_le_bytes([image[0], image[1]]), 0x0001);
+        assert_eq!(u16::from_le_bytes([image[2], image[3]]), 0x8000);
+        // 0x0002_8000 has a negative low half, so the high half rounds up.
+        assert_eq!(u16::from_le_bytes([image[4], image[5]]), 0x0003);
+    }
+
+    #[test]
+    fn applies_arm_mov32() {
+        // movw r1, #0x5678; movt r1, #0x1234
+        let movw = 0xE300_0000 | 0x5 << 16 | 1 << 12 | 0x678;
+        let movt = 0xE340_0000 | 0x1 << 16 | 1 << 12 | 0x234;
+
+        let mut image = [0u8; 8];
+        image[0..4].copy_from_slice(&u32::to_le_bytes(movw));
+        image[4..8].copy_from_slice(&u32::to_le_bytes(movt));
+
+        apply_relocation(
+            &mut image,
+            &reloc(0, IMAGE_REL_BASED_ARM_MOV32),
+            0x1000_0001,
+        )
+        .unwrap();
+
+        let movw = u32::from_le_bytes(image[0..4].try_into().unwrap());
+        let movt = u32::from_le_bytes(image[4..8].try_into().unwrap());
+        assert_eq!(arm_mov_imm(movw), 0x5679);
+        assert_eq!(arm_mov_imm(movt), 0x2234);
+        assert_eq!(movw & !0x000F_0FFF, 0xE300_1000);
+        assert_eq!(movt & !0x000F_0FFF, 0xE340_1000);
+    }
+
+    #[test]
+    fn applies_thumb_mov32() {
+        let (movw_lo, movw_hi) = thumb_mov_with_imm(0xF240, 2 << 8, 0xFFFF);
+        let (movt_lo, movt_hi) = thumb_mov_with_imm(0xF2C0, 2 << 8, 0x1234);
+
+        let mut image = [0u8; 8];
+        for (idx, halfword) in [movw_lo, movw_hi, movt_lo, movt_hi].iter().enumerate() {
+            image[idx * 2..idx * 2 + 2].copy_from_slice(&halfword.to_le_bytes());
+        }
+
+        apply_relocation(&mut image, &reloc(0, IMAGE_REL_BASED_THUMB_MOV32), 1).unwrap();
+
+        let halfwords: [u16; 4] =
+            std::array::from_fn(|idx| u16::from_le_bytes([image[idx * 2], image[idx * 2 + 1]]));
+        assert_eq!(thumb_mov_imm(halfwords[0], halfwords[1]), 0x0000);
+        assert_eq!(thumb_mov_imm(halfwords[2], halfwords[3]), 0x1235);
+        // Opcodes and the destination register are untouched.
+        assert_eq!(halfwords[0] & !0x040F, 0xF240);
+        assert_eq!(halfwords[1] & !0x70FF, 2 << 8);
+        assert_eq!(halfwords[2] & !0x040F, 0xF2C0);
+    }
+
+    #[test]
+    fn rejects_moving_stripped_images() {
+        let mut image = IMAGE_BASE.to_le_bytes();
+        let table = FixupTable {
+            relocs_stripped: true,
+            ..table(Vec::new())
+        };
+
+        assert!(table.apply_relocations(&mut image, IMAGE_BASE).is_ok());
+        assert!(matches!(
+            table.apply_relocations(&mut image, IMAGE_BASE + 0x10000),
+            Err(Error::RelocationsStripped(IMAGE_BASE))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_relocations() {
+        let mut image = [0u8; 0x10];
+
+        assert!(matches!(
+            table(vec![reloc(0xC, IMAGE_REL_BASED_DIR64)]).apply_relocations(&mut image, 0),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            apply_relocation(&mut image, &reloc(u32::MAX, IMAGE_REL_BASED_HIGHLOW), 1),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            table(vec![reloc(0, 9)]).apply_relocations(&mut image, 0),
+            Err(Error::UnsupportedRelocation { kind: 9, rva: 0 })
+        ));
+    }
+
+    #[test]
+    fn applies_imports() {
+        let table = FixupTable {
+            imports: vec![
+                ImportFixup {
+                    thunk_rva: 0,
+                    module: "KERNEL32.dll".to_string(),
+                    import: ImportName::Name {
+                        hint: 0,
+                        name: "Sleep".to_string(),
+                    },
+                },
+                ImportFixup {
+                    thunk_rva: 8,
+                    module: "ws2_32.dll".to_string(),
+                    import: ImportName::Ordinal(23),
+                },
+            ],
+            ..table(Vec::new())
+        };
+
+        let mut image = [0u8; 0x10];
+        table
+            .apply_imports(&mut image, |import| match &import.import {
+                ImportName::Name { name, .. } if name == "Sleep" => Some(0x7FFA_0000_1000),
+                ImportName::Ordinal(23) => Some(0x7FFA_0001_2000),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(image[..8], 0x7FFA_0000_1000u64.to_le_bytes());
+        assert_eq!(image[8..], 0x7FFA_0001_2000u64.to_le_bytes());
+
+        let err = table
+            .apply_imports(&mut image, |import| {
+                (import.module == "KERNEL32.dll").then_some(1)
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "missing import #23 from ws2_32.dll");
+
+        let mut image = [0u8; 0xC];
+        assert!(matches!(
+            table.apply_imports(&mut image, |_| Some(1)),
+            Err(Error::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn normalizes_module_names() {
+        assert_eq!(normalize_module_name("KERNEL32.dll"), "kernel32.dll");
+        assert_eq!(
+            normalize_module_name("Kernel32.DLL"),
+            normalize_module_name("kernel32.dll")
+        );
+    }
+}
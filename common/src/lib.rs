@@ -0,0 +1,8 @@
+//! Wire formats and image fixup logic shared by the service and the client.
+
+pub mod envelope;
+pub mod error;
+pub mod fixups;
+pub mod protection;
+
+pub use error::{Error, Result};
@@ -0,0 +1,310 @@
+//! Page protections for a mapped image, computed from its section characteristics, and the pages
+//! that can be decommitted once it is initialized.
+//!
+//! Sections are not page-aligned in size, so several sections can share a page. Such pages get
+//! the union of the sections' access rights, which keeps the result independent of section order
+//! and never takes access away from a section. Pages not covered by any section, such as the
+//! headers, are read-only.
+
+use crate::envelope::{DiscardableSection, Region};
+
+pub const PAGE_SIZE: u32 = 0x1000;
+
+pub const IMAGE_SCN_MEM_DISCARDABLE: u32 = 0x0200_0000;
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+pub const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+pub const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+pub const PAGE_NOACCESS: u32 = 0x01;
+pub const PAGE_READONLY: u32 = 0x02;
+pub const PAGE_READWRITE: u32 = 0x04;
+pub const PAGE_EXECUTE: u32 = 0x10;
+pub const PAGE_EXECUTE_READ: u32 = 0x20;
+pub const PAGE_EXECUTE_READWRITE: u32 = 0x40;
+
+const ACCESS_MASK: u32 = IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE;
+
+/// A page-aligned range of the image and the protection to apply to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectionRange {
+    pub rva: u32,
+    pub size: u32,
+    pub protection: u32,
+}
+
+/// Returns the page protection matching a section's `IMAGE_SCN_MEM_*` characteristics. Writable
+/// pages are always readable on Windows.
+pub fn section_protection(characteristics: u32) -> u32 {
+    let execute = characteristics & IMAGE_SCN_MEM_EXECUTE != 0;
+    let read = characteristics & IMAGE_SCN_MEM_READ != 0;
+    let write = characteristics & IMAGE_SCN_MEM_WRITE != 0;
+
+    match (execute, read, write) {
+        (true, _, true) => PAGE_EXECUTE_READWRITE,
+        (true, true, false) => PAGE_EXECUTE_READ,
+        (true, false, false) => PAGE_EXECUTE,
+        (false, _, true) => PAGE_READWRITE,
+        (false, true, false) => PAGE_READONLY,
+        (false, false, false) => PAGE_NOACCESS,
+    }
+}
+
+pub fn align_down(value: u32) -> u32 {
+    value & !(PAGE_SIZE - 1)
+}
+
+pub fn align_up(value: u32) -> u32 {
+    value.saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Returns the protections covering every page of an image of `size_of_image` bytes, with
+/// adjacent pages of equal protection merged. Sections outside the image are clipped.
+pub fn plan_protections(size_of_image: u32, regions: &[Region]) -> Vec<ProtectionRange> {
+    let image_end = align_up(size_of_image);
+    let page_count = (image_end / PAGE_SIZE) as usize;
+
+    // Access rights per page, `None` for pages no section covers.
+    let mut pages: Vec<Option<u32>> = vec![None; page_count];
+
+    for region in regions {
+        let start = align_down(region.rva).min(image_end);
+        let end = align_up(region.rva.saturating_add(region.size)).min(image_end);
+
+        for page in &mut pages[(start / PAGE_SIZE) as usize..(end / PAGE_SIZE) as usize] {
+            *page = Some(page.unwrap_or(0) | (region.characteristics & ACCESS_MASK));
+        }
+    }
+
+    let mut ranges: Vec<ProtectionRange> = Vec::new();
+
+    for (idx, page) in pages.into_iter().enumerate() {
+        let protection = match page {
+            Some(access) => section_protection(access),
+            None => PAGE_READONLY,
+        };
+
+        match ranges.last_mut() {
+            Some(range) if range.protection == protection => range.size += PAGE_SIZE,
+            _ => ranges.push(ProtectionRange {
+                rva: idx as u32 * PAGE_SIZE,
+                size: PAGE_SIZE,
+                protection,
+            }),
+        }
+    }
+
+    ranges
+}
+
+/// Returns the page-aligned ranges that only `discarded` sections occupy. Pages shared with other
+/// sections, and the headers, are kept.
+pub fn plan_decommit(
+    size_of_image: u32,
+    regions: &[Region],
+    discarded: &[DiscardableSection],
+) -> Vec<(u32, u32)> {
+    let image_end = align_up(size_of_image);
+    let page_range = |rva: u32, size: u32| {
+        let start = align_down(rva).min(image_end) / PAGE_SIZE;
+        let end = align_up(rva.saturating_add(size)).min(image_end) / PAGE_SIZE;
+        start as usize..end as usize
+    };
+
+    let mut pages = vec![false; (image_end / PAGE_SIZE) as usize];
+
+    for section in discarded {
+        pages[page_range(section.rva, section.size)].fill(true);
+    }
+
+    // The headers precede the first section.
+    let first_section = regions.iter().map(|region| region.rva).min().unwrap_or(0);
+    pages[page_range(0, first_section.max(1))].fill(false);
+
+    for region in regions {
+        let is_discarded = discarded.iter().any(|section| section.rva == region.rva);
+        if !is_discarded {
+            pages[page_range(region.rva, region.size)].fill(false);
+        }
+    }
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+
+    for (idx, _) in pages.iter().enumerate().filter(|(_, discard)| **discard) {
+        let rva = idx as u32 * PAGE_SIZE;
+        match ranges.last_mut() {
+            Some((start, size)) if *start + *size == rva => *size += PAGE_SIZE,
+            _ => ranges.push((rva, PAGE_SIZE)),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: u32 = IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ;
+    const RDATA: u32 = IMAGE_SCN_MEM_READ;
+    const DATA: u32 = IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE;
+
+    fn region(rva: u32, size: u32, characteristics: u32) -> Region {
+        Region {
+            rva,
+            size,
+            characteristics,
+        }
+    }
+
+    fn discardable(rva: u32, size: u32) -> DiscardableSection {
+        DiscardableSection {
+            name: ".reloc".to_string(),
+            rva,
+            size,
+        }
+    }
+
+    fn range(rva: u32, size: u32, protection: u32) -> ProtectionRange {
+        ProtectionRange {
+            rva,
+            size,
+            protection,
+        }
+    }
+
+    #[test]
+    fn maps_characteristics_to_protections() {
+        assert_eq!(section_protection(CODE), PAGE_EXECUTE_READ);
+        assert_eq!(section_protection(IMAGE_SCN_MEM_EXECUTE), PAGE_EXECUTE);
+        assert_eq!(section_protection(CODE | DATA), PAGE_EXECUTE_READWRITE);
+        assert_eq!(
+            section_protection(IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_WRITE),
+            PAGE_EXECUTE_READWRITE
+        );
+        assert_eq!(section_protection(RDATA), PAGE_READONLY);
+        assert_eq!(section_protection(DATA), PAGE_READWRITE);
+        assert_eq!(section_protection(IMAGE_SCN_MEM_WRITE), PAGE_READWRITE);
+        assert_eq!(section_protection(0), PAGE_NOACCESS);
+    }
+
+    #[test]
+    fn protects_headers_and_gaps_read_only() {
+        let ranges = plan_protections(
+            0x5000,
+            &[region(0x1000, 0x1000, CODE), region(0x3000, 0x800, DATA)],
+        );
+
+        assert_eq!(
+            ranges,
+            [
+                range(0x0000, 0x1000, PAGE_READONLY),
+                range(0x1000, 0x1000, PAGE_EXECUTE_READ),
+                range(0x2000, 0x1000, PAGE_READONLY),
+                range(0x3000, 0x1000, PAGE_READWRITE),
+                range(0x4000, 0x1000, PAGE_READONLY),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_access_of_sections_sharing_a_page() {
+        // Low-alignment images pack sections into the same pages.
+        let regions = [
+            region(0x1000, 0x1200, CODE),
+            region(0x2200, 0x100, RDATA),
+            region(0x2300, 0x100, DATA),
+            region(0x2400, 0x1000, RDATA),
+        ];
+        let expected = [
+            range(0x0000, 0x1000, PAGE_READONLY),
+            range(0x1000, 0x1000, PAGE_EXECUTE_READ),
+            range(0x2000, 0x1000, PAGE_EXECUTE_READWRITE),
+            range(0x3000, 0x1000, PAGE_READONLY),
+        ];
+
+        assert_eq!(plan_protections(0x4000, &regions), expected);
+
+        let mut reversed = regions;
+        reversed.reverse();
+        assert_eq!(plan_protections(0x4000, &reversed), expected);
+    }
+
+    #[test]
+    fn merges_adjacent_pages_of_equal_protection() {
+        let ranges = plan_protections(
+            0x4000,
+            &[region(0x1000, 0x1000, RDATA), region(0x2000, 0x2000, RDATA)],
+        );
+
+        assert_eq!(ranges, [range(0x0000, 0x4000, PAGE_READONLY)]);
+    }
+
+    #[test]
+    fn clips_sections_to_the_image() {
+        let ranges = plan_protections(
+            0x2800,
+            &[
+                region(0x1000, 0x8000, DATA),
+                region(0x9000, 0x1000, CODE),
+                region(0xFFFF_F000, 0x2000, CODE),
+            ],
+        );
+
+        assert_eq!(
+            ranges,
+            [
+                range(0x0000, 0x1000, PAGE_READONLY),
+                range(0x1000, 0x2000, PAGE_READWRITE),
+            ]
+        );
+        assert!(plan_protections(0, &[region(0x1000, 0x1000, CODE)]).is_empty());
+    }
+
+    #[test]
+    fn decommits_pages_only_discarded_sections_use() {
+        let regions = [
+            region(0x1000, 0x1000, CODE),
+            region(0x2000, 0x1800, DATA),
+            region(0x3800, 0x1800, RDATA | IMAGE_SCN_MEM_DISCARDABLE),
+        ];
+
+        // The page at 0x3000 is shared with .data.
+        assert_eq!(
+            plan_decommit(0x5000, &regions, &[discardable(0x3800, 0x1800)]),
+            [(0x4000, 0x1000)]
+        );
+        assert_eq!(
+            plan_decommit(0x7000, &regions, &[discardable(0x3800, 0x1800)]),
+            [(0x4000, 0x1000)]
+        );
+        assert!(plan_decommit(0x5000, &regions, &[]).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_headers() {
+        let regions = [
+            region(0x400, 0x1C00, RDATA | IMAGE_SCN_MEM_DISCARDABLE),
+            region(0x2000, 0x1000, CODE),
+        ];
+
+        // The first section shares its page with the headers.
+        assert_eq!(
+            plan_decommit(0x3000, &regions, &[discardable(0x400, 0x1C00)]),
+            [(0x1000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn clips_discarded_sections_to_the_image() {
+        let regions = [
+            region(0x1000, 0x1000, CODE),
+            region(0x2000, 0x4000, RDATA | IMAGE_SCN_MEM_DISCARDABLE),
+        ];
+
+        assert_eq!(
+            plan_decommit(0x3000, &regions, &[discardable(0x2000, 0x4000)]),
+            [(0x2000, 0x1000)]
+        );
+        assert!(plan_decommit(0x3000, &regions, &[discardable(0xFFFF_F000, 0x2000)]).is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+//! Reports the response size of both library routes for each supported `Accept-Encoding`.
+//!
+//! Start the service with a sample DLL in `assets/libraries`, then run:
+//!
+//! ```sh
+//! NET_SYRINGE_LIBRARY=test.dll cargo bench --bench compression
+//! ```
+
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    header::{ACCEPT, ACCEPT_ENCODING, CONTENT_TYPE},
+};
+use serde_json::{Value, json};
+use std::time::Instant;
+
+const ENCODINGS: [&str; 3] = ["identity", "gzip", "zstd"];
+
+fn measure(name: &str, request: impl Fn() -> RequestBuilder) -> reqwest::Result<()> {
+    let mut identity_size = 0;
+
+    for encoding in ENCODINGS {
+        let start_time = Instant::now();
+        let response = request()
+            .header(ACCEPT_ENCODING, encoding)
+            .send()?
+            .error_for_status()?;
+        let size = response.bytes()?.len();
+        let elapsed = start_time.elapsed();
+
+        if encoding == "identity" {
+            identity_size = size;
+        }
+
+        println!(
+            "{:<24} {:<10} {:>10} bytes {:>6.1}% saved {:>8.2?}",
+            name,
+            encoding,
+            size,
+            100.0 - size as f64 * 100.0 / identity_size.max(1) as f64,
+            elapsed
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let host = std::env::var("NET_SYRINGE_HOST").unwrap_or("http://localhost:3000".to_string());
+    let library_id = std::env::var("NET_SYRINGE_LIBRARY").unwrap_or("test.dll".to_string());
+
+    let client = Client::new();
+    let library_url = format!("{}/libraries/{}", host, library_id);
+
+    let mut library: Value = serde_json::from_slice(
+        &client
+            .get(&library_url)
+            .send()?
+            .error_for_status()?
+            .bytes()?,
+    )?;
+
+    // The addresses only need to be present, the image is never executed.
+    let mut address = 0x7FF0_0000_0000u64;
+    for imports in library["imports"]
+        .as_object_mut()
+        .ok_or("invalid library response")?
+        .values_mut()
+    {
+        for import in imports.as_array_mut().ok_or("invalid library response")? {
+            import["address"] = json!(address);
+            address += 0x10;
+        }
+    }
+
+    let mapping = serde_json::to_vec(&json!({
+        "allocation_base": 0x1_8000_0000u64,
+        "imports": library["imports"],
+    }))?;
+
+    measure("metadata", || client.get(&library_url))?;
+
+    for (name, accept) in [
+        ("mapping (raw)", "application/octet-stream"),
+        ("mapping (envelope)", "application/vnd.net-syringe.image"),
+    ] {
+        measure(name, || {
+            client
+                .get(format!("{}/mapping", library_url))
+                .header(ACCEPT, accept)
+                .header(CONTENT_TYPE, "application/json")
+                .body(mapping.clone())
+        })?;
+    }
+
+    Ok(())
+}
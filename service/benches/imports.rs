@@ -0,0 +1,197 @@
+//! Compares import resolution with the per-request index against a linear search of the
+//! supplied imports, over synthetic DLLs with large import tables.
+//!
+//! ```sh
+//! cargo bench --bench imports
+//! ```
+
+use pelite::pe64::{Pe, PeFile, imports::Import};
+use service::{dto, library::Library, loader::resolve_imports};
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+const DLL_NAME: &str = "bench.dll";
+const IMPORT_COUNTS: [usize; 4] = [100, 1_000, 5_000, 20_000];
+const ITERATIONS: u32 = 5;
+
+const SIZE_OF_HEADERS: usize = 0x400;
+const SECTION_RVA: usize = 0x1000;
+
+fn import_name(idx: usize) -> String {
+    format!("BenchFunction{:05}", idx)
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+fn write(image: &mut [u8], offset: usize, bytes: &[u8]) {
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Builds a PE32+ DLL with a single `.idata` section importing `count` functions by name.
+fn build_dll(count: usize) -> Vec<u8> {
+    // Descriptor and terminator, then the lookup and address tables, the DLL name and hint/names.
+    let int_rva = 2 * 20;
+    let iat_rva = int_rva + (count + 1) * 8;
+    let dll_name_rva = iat_rva + (count + 1) * 8;
+    let mut names_rva = align_up(dll_name_rva + DLL_NAME.len() + 1, 2);
+
+    let mut section = vec![0u8; names_rva];
+
+    write(&mut section, 0, &(SECTION_RVA + int_rva).to_le_bytes()[..4]);
+    write(
+        &mut section,
+        12,
+        &(SECTION_RVA + dll_name_rva).to_le_bytes()[..4],
+    );
+    write(
+        &mut section,
+        16,
+        &(SECTION_RVA + iat_rva).to_le_bytes()[..4],
+    );
+    write(&mut section, dll_name_rva, DLL_NAME.as_bytes());
+
+    for idx in 0..count {
+        let thunk = ((SECTION_RVA + names_rva) as u64).to_le_bytes();
+        write(&mut section, int_rva + idx * 8, &thunk);
+        write(&mut section, iat_rva + idx * 8, &thunk);
+
+        let name = import_name(idx);
+        section.resize(align_up(names_rva + 2 + name.len() + 1, 2), 0);
+        write(&mut section, names_rva, &(idx as u16).to_le_bytes());
+        write(&mut section, names_rva + 2, name.as_bytes());
+        names_rva = section.len();
+    }
+
+    let virtual_size = section.len();
+    let raw_size = align_up(virtual_size, 0x200);
+    let size_of_image = SECTION_RVA + align_up(virtual_size, 0x1000);
+
+    let mut image = vec![0u8; SIZE_OF_HEADERS + raw_size];
+
+    // DOS header
+    write(&mut image, 0, b"MZ");
+    write(&mut image, 0x3C, &0x40u32.to_le_bytes());
+
+    // NT headers
+    let nt = 0x40;
+    write(&mut image, nt, b"PE\0\0");
+    write(&mut image, nt + 4, &0x8664u16.to_le_bytes());
+    write(&mut image, nt + 6, &1u16.to_le_bytes());
+    write(&mut image, nt + 20, &0xF0u16.to_le_bytes());
+    write(&mut image, nt + 22, &0x2022u16.to_le_bytes());
+
+    // Optional header
+    let opt = nt + 24;
+    write(&mut image, opt, &0x20Bu16.to_le_bytes());
+    write(&mut image, opt + 24, &0x1_8000_0000u64.to_le_bytes());
+    write(&mut image, opt + 32, &0x1000u32.to_le_bytes());
+    write(&mut image, opt + 36, &0x200u32.to_le_bytes());
+    write(&mut image, opt + 48, &6u16.to_le_bytes());
+    write(&mut image, opt + 56, &(size_of_image as u32).to_le_bytes());
+    write(
+        &mut image,
+        opt + 60,
+        &(SIZE_OF_HEADERS as u32).to_le_bytes(),
+    );
+    write(&mut image, opt + 68, &2u16.to_le_bytes());
+    write(&mut image, opt + 108, &16u32.to_le_bytes());
+    write(&mut image, opt + 120, &(SECTION_RVA as u32).to_le_bytes());
+    write(&mut image, opt + 124, &40u32.to_le_bytes());
+
+    // Section header
+    let sect = opt + 0xF0;
+    write(&mut image, sect, b".idata");
+    write(&mut image, sect + 8, &(virtual_size as u32).to_le_bytes());
+    write(&mut image, sect + 12, &(SECTION_RVA as u32).to_le_bytes());
+    write(&mut image, sect + 16, &(raw_size as u32).to_le_bytes());
+    write(
+        &mut image,
+        sect + 20,
+        &(SIZE_OF_HEADERS as u32).to_le_bytes(),
+    );
+    write(&mut image, sect + 36, &0xC000_0040u32.to_le_bytes());
+
+    write(&mut image, SIZE_OF_HEADERS, &section);
+
+    image
+}
+
+/// Import addresses as the client supplies them, in reverse order of the import table.
+fn client_imports(count: usize) -> HashMap<String, Vec<dto::Import>> {
+    let imports = (0..count)
+        .rev()
+        .map(|idx| dto::Import::ByName {
+            hint: idx,
+            name: import_name(idx),
+            address: Some(0x7FF0_0000_0000 + idx as u64 * 0x10),
+        })
+        .collect();
+
+    HashMap::from([(DLL_NAME.to_string(), imports)])
+}
+
+/// Searches the supplied imports for every thunk, as the service did before indexing them.
+fn resolve_imports_linear(
+    pe: &PeFile,
+    mapped_image: &mut [u8],
+    exports: &HashMap<String, Vec<dto::Import>>,
+) -> Option<()> {
+    for desc in pe.imports().ok()? {
+        let exports = exports.get(&desc.dll_name().ok()?.to_string())?;
+
+        for (idx, imp) in desc.int().ok()?.enumerate() {
+            let Import::ByName { name, .. } = imp.ok()? else {
+                return None;
+            };
+
+            let address = exports.iter().find_map(|imp| match imp {
+                dto::Import::ByName {
+                    name: imp_name,
+                    address,
+                    ..
+                } if name == imp_name => *address,
+                _ => None,
+            })?;
+
+            let thunk_rva = desc.image().FirstThunk as usize + idx * 8;
+            mapped_image[thunk_rva..thunk_rva + 8].copy_from_slice(&address.to_le_bytes());
+        }
+    }
+
+    Some(())
+}
+
+fn measure(name: &str, count: usize, mut run: impl FnMut()) {
+    let start_time = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    let elapsed = start_time.elapsed() / ITERATIONS;
+
+    println!("{:<8} {:>6} imports {:>12.2?}", name, count, elapsed);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for count in IMPORT_COUNTS {
+        let library = Library {
+            file_data: build_dll(count),
+            ..Default::default()
+        };
+        let pe = library.pe_file()?;
+        let exports = client_imports(count);
+        let mut mapped_image = vec![0u8; pe.optional_header().SizeOfImage as usize];
+
+        measure("linear", count, || {
+            black_box(resolve_imports_linear(&pe, &mut mapped_image, &exports))
+                .expect("linear resolution failed");
+        });
+
+        measure("indexed", count, || {
+            black_box(resolve_imports(&pe, &mut mapped_image, &exports))
+                .expect("indexed resolution failed");
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Rebuilds lld-link.dll and mingw.dll from fixture.s with the LLVM tools, the rust-lld that ships
+# with the Rust toolchain and GNU ld. msvc.dll is not built here, see README.md.
+set -eu
+
+cd "$(dirname "$0")"
+
+LLD=${LLD:-$(rustc --print sysroot)/lib/rustlib/$(rustc -vV | sed -n 's/^host: //p')/bin/rust-lld}
+# Any binutils ld with the i386pep emulation, such as x86_64-w64-mingw32-ld.
+GNU_LD=${GNU_LD:-ld.bfd}
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+
+for case in upper lower; do
+    llvm-dlltool -m i386:x86-64 -d kernel32-$case.def -l "$TMP/kernel32-$case.lib"
+done
+
+llvm-mc -triple x86_64-pc-windows-msvc -filetype obj fixture.s -o "$TMP/fixture.obj"
+"$LLD" -flavor link /dll /machine:x64 /brepro /nodefaultlib /entry:DllMain /def:fixture.def \
+    /out:lld-link.dll /implib:"$TMP/fixture.lib" \
+    "$TMP/fixture.obj" "$TMP/kernel32-upper.lib" "$TMP/kernel32-lower.lib"
+
+llvm-mc -triple x86_64-w64-windows-gnu -filetype obj fixture.s -o "$TMP/fixture.o"
+"$GNU_LD" -m i386pep --shared --strip-all --no-insert-timestamp -e DllMain -o mingw.dll \
+    "$TMP/fixture.o" fixture.def "$TMP/kernel32-upper.lib" "$TMP/kernel32-lower.lib"
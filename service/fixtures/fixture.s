@@ -0,0 +1,35 @@
+    .intel_syntax noprefix
+
+    .text
+    .globl DllMain
+DllMain:
+    mov eax, 1
+    ret
+
+    .globl Init
+Init:
+    sub rsp, 40
+    call qword ptr [rip + __imp_GetCurrentProcessId]
+    mov dword ptr [rip + buffer], eax
+    mov ecx, 0
+    call qword ptr [rip + __imp_Sleep]
+    mov rax, qword ptr [rip + counter_ptr]
+    add dword ptr [rax], 1
+    mov eax, 1
+    add rsp, 40
+    ret
+
+    .data
+counter:
+    .long 0
+    .p2align 3
+counter_ptr:
+    .quad counter
+
+    .bss
+buffer:
+    .zero 0x1800
+
+    .section .rdata,"dr"
+message:
+    .asciz "net-syringe fixture"
@@ -0,0 +1,41 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use service::{
+    dto,
+    library::Library,
+    loader::{get_fixup_table, get_library_imports, map_image, resolve_imports},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let library = Library {
+        file_data: data.to_vec(),
+        ..Default::default()
+    };
+
+    let Ok(pe) = library.pe_file() else {
+        return;
+    };
+
+    let Ok(mut mapped_image) = map_image(&pe, &library) else {
+        return;
+    };
+
+    if let Ok(fixups) = get_fixup_table(&pe) {
+        let _ = fixups.apply_imports(&mut mapped_image, |fixup| Some(fixup.thunk_rva as u64));
+    }
+
+    let Ok(mut exports) = get_library_imports(&pe) else {
+        return;
+    };
+
+    for (idx, import) in exports.values_mut().flatten().enumerate() {
+        match import {
+            dto::Import::ByName { address, .. } | dto::Import::ByOrdinal { address, .. } => {
+                *address = Some(idx as u64)
+            }
+        }
+    }
+
+    let _ = resolve_imports(&pe, &mut mapped_image, &exports);
+});
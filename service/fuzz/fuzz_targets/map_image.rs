@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use service::{library::Library, loader::map_image};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, file_data)) = data.split_first() else {
+        return;
+    };
+
+    let library = Library {
+        file_data: file_data.to_vec(),
+        map_pe_headers: flags & 1 != 0,
+        ..Default::default()
+    };
+
+    if let Ok(pe) = library.pe_file() {
+        let _ = map_image(&pe, &library);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use service::{
+    dto,
+    library::Library,
+    loader::{apply_relocations, map_image, patch_guard_cf, resolve_imports},
+};
+use std::sync::LazyLock;
+
+/// Import addresses and allocation bases come from the request body, the image is a fixture.
+static LIBRARY: LazyLock<Library> = LazyLock::new(|| Library {
+    file_data: include_bytes!("../../fixtures/lld-link.dll").to_vec(),
+    ..Default::default()
+});
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(body) = serde_json::from_slice::<dto::GetLibraryMapping>(data) else {
+        return;
+    };
+
+    let pe = LIBRARY.pe_file().expect("fixture parses");
+    let mut mapped_image = map_image(&pe, &LIBRARY).expect("fixture maps");
+
+    let _ = resolve_imports(&pe, &mut mapped_image, &body.imports);
+    let _ = apply_relocations(&pe, &mut mapped_image, body.allocation_base);
+    let _ = patch_guard_cf(&pe, &mut mapped_image, body.guard_cf.as_ref());
+});
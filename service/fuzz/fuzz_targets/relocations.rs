@@ -0,0 +1,35 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use service::{
+    library::Library,
+    loader::{apply_relocations, get_fixup_table, map_image},
+};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+
+    let (base, file_data) = data.split_at(8);
+    let allocation_base = u64::from_le_bytes(base.try_into().unwrap());
+
+    let library = Library {
+        file_data: file_data.to_vec(),
+        ..Default::default()
+    };
+
+    let Ok(pe) = library.pe_file() else {
+        return;
+    };
+
+    let Ok(mut mapped_image) = map_image(&pe, &library) else {
+        return;
+    };
+
+    let _ = apply_relocations(&pe, &mut mapped_image, allocation_base);
+
+    if let Ok(fixups) = get_fixup_table(&pe) {
+        let _ = fixups.apply_relocations(&mut mapped_image, allocation_base);
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use service::{
+    library::Library,
+    loader::{init_security_cookie, map_image},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let library = Library {
+        file_data: data.to_vec(),
+        ..Default::default()
+    };
+
+    let Ok(pe) = library.pe_file() else {
+        return;
+    };
+
+    if let Ok(mut mapped_image) = map_image(&pe, &library) {
+        let _ = init_security_cookie(&pe, &mut mapped_image);
+    }
+});
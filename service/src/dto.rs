@@ -1,7 +1,52 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// First protocol version whose clients accept libraries without an entry point.
+pub const OPTIONAL_ENTRY_POINT_VERSION: u32 = 2;
+
+pub const PROTOCOL_VERSION_HEADER: &str = "x-net-syringe-protocol";
+pub const PROTOCOL_FEATURES_HEADER: &str = "x-net-syringe-features";
+
+/// Features implemented by this service, which reports the exception table in the image envelope
+/// for clients that register it. Libraries with TLS callbacks are refused until they are run.
+pub const SUPPORTED_FEATURES: &[Feature] = &[Feature::ExceptionTables];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    TlsCallbacks,
+    ExceptionTables,
+    Pe32,
+}
+
+impl Feature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::TlsCallbacks => "tls-callbacks",
+            Feature::ExceptionTables => "exception-tables",
+            Feature::Pe32 => "pe32",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tls-callbacks" => Ok(Feature::TlsCallbacks),
+            "exception-tables" => Ok(Feature::ExceptionTables),
+            "pe32" => Ok(Feature::Pe32),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "type")]
 pub enum Import {
     #[serde(rename = "name")]
@@ -19,23 +64,88 @@ pub enum Import {
     },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct Region {
     pub rva: u32,
     pub size: u32,
     pub characteristics: u32,
 }
 
-#[derive(Debug, Serialize)]
+/// A section marked `IMAGE_SCN_MEM_DISCARDABLE`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiscardableSection {
+    pub name: String,
+    pub rva: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct GetLibraryResponse {
     pub size_of_image: u32,
-    pub entry_point: u32,
+    /// `None` for libraries without an entry point, such as resource-only DLLs.
+    pub entry_point: Option<u32>,
+    pub image_base: u64,
+    /// The image can only be mapped at `image_base`.
+    pub relocs_stripped: bool,
     pub imports: HashMap<String, Vec<Import>>,
     pub regions: Vec<Region>,
+    /// Sections that can be decommitted once the library is initialized.
+    pub discardable: Vec<DiscardableSection>,
+    /// Hex-encoded SHA-256 of the library file.
+    pub sha256: String,
+}
+
+/// Addresses stored in the Control Flow Guard function pointers of images built with `/guard:cf`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct GuardCfFunctions {
+    /// Stored in `__guard_check_icall_fptr`.
+    pub check_function: u64,
+    /// Stored in `__guard_dispatch_icall_fptr`.
+    pub dispatch_function: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct GetLibraryMapping {
     pub allocation_base: u64,
     pub imports: HashMap<String, Vec<Import>>,
+    #[serde(default)]
+    pub guard_cf: Option<GuardCfFunctions>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetLibraryImage {
+    /// Address stored in `__guard_check_icall_fptr`, required for images built with `/guard:cf`.
+    pub guard_cf_check_function: Option<u64>,
+    /// Address stored in `__guard_dispatch_icall_fptr`, required for images built with `/guard:cf`.
+    pub guard_cf_dispatch_function: Option<u64>,
+}
+
+impl GetLibraryImage {
+    pub fn guard_cf(&self) -> Option<GuardCfFunctions> {
+        Some(GuardCfFunctions {
+            check_function: self.guard_cf_check_function?,
+            dispatch_function: self.guard_cf_dispatch_function?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetLibraryExport {
+    /// Name of the exported function.
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GetLibraryExportResponse {
+    pub name: String,
+    pub rva: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GetVersionResponse {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub features: Vec<Feature>,
 }
@@ -9,13 +9,24 @@ pub enum MissingImportError {
 }
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error(transparent)]
     PeError(#[from] pelite::Error),
     #[error(transparent)]
     MissingImport(#[from] MissingImportError),
+    #[error(transparent)]
+    Fixup(#[from] net_syringe_common::Error),
     #[error("out of range")]
     OutOfRange,
+    #[error("invalid file or section alignment")]
+    InvalidAlignment,
+    #[error("image size {0:#x} exceeds the mapping limit")]
+    ImageTooLarge(u32),
+    #[error(
+        "library is built with Control Flow Guard and no guard functions were supplied, rebuild it without /guard:cf"
+    )]
+    ControlFlowGuard,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
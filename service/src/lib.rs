@@ -0,0 +1,51 @@
+use axum::{Json, Router, http::StatusCode, routing::get};
+use library::LibraryDir;
+use std::path::PathBuf;
+use tower_http::{compression::CompressionLayer, trace::TraceLayer};
+use utoipa::OpenApi;
+
+pub mod dto;
+pub mod error;
+pub mod library;
+pub mod loader;
+pub mod openapi;
+pub mod protocol;
+pub mod routes;
+
+#[cfg(test)]
+mod testing;
+
+/// The service API, serving the libraries in `assets/libraries`.
+pub fn router() -> Router {
+    router_with_libraries("assets/libraries")
+}
+
+/// The service API, serving the libraries in `dir`.
+pub fn router_with_libraries<P: Into<PathBuf>>(dir: P) -> Router {
+    Router::new()
+        .route("/", get(|| async { StatusCode::OK }))
+        .route("/version", get(routes::version::get_version))
+        .route(
+            "/openapi.json",
+            get(|| async { Json(openapi::ApiDoc::openapi()) }),
+        )
+        .route(
+            "/libraries/{library_id}",
+            get(routes::libraries::get_library),
+        )
+        .route(
+            "/libraries/{library_id}/export",
+            get(routes::libraries::get_library_export),
+        )
+        .route(
+            "/libraries/{library_id}/mapping",
+            get(routes::libraries::get_library_mapping),
+        )
+        .route(
+            "/libraries/{library_id}/image",
+            get(routes::libraries::get_library_image),
+        )
+        .layer(CompressionLayer::new())
+        .layer(TraceLayer::new_for_http())
+        .with_state(LibraryDir::new(dir))
+}
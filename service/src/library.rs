@@ -1,5 +1,25 @@
+use pelite::{image::IMAGE_SECTION_HEADER, pe32, pe64};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::{fs, io};
 
+/// Directory the service loads libraries from, shared with the handlers as router state.
+#[derive(Debug, Clone)]
+pub struct LibraryDir(Arc<PathBuf>);
+
+impl LibraryDir {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self(Arc::new(path.into()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
 pub struct Library {
     pub file_data: Vec<u8>,
     pub map_pe_headers: bool,
@@ -7,18 +27,50 @@ pub struct Library {
 }
 
 impl Library {
-    pub async fn get(library_id: &str) -> io::Result<Self> {
+    pub async fn get(dir: &LibraryDir, library_id: &str) -> io::Result<Self> {
         if !is_valid_library_id(library_id) {
             return Err(io::Error::from(io::ErrorKind::InvalidInput));
         }
 
-        let file_data = fs::read(format!("assets/libraries/{}", library_id)).await?;
+        let file_data = fs::read(dir.path().join(library_id)).await?;
 
         Ok(Self {
             file_data,
             ..Default::default()
         })
     }
+
+    pub fn pe_file(&self) -> pelite::Result<pe64::PeFile<'_>> {
+        use pelite::pe64::Pe;
+
+        check_header_alignment(&self.file_data, 8)?;
+        let pe = pe64::PeFile::from_bytes(&self.file_data)?;
+        check_section_alignment(pe.section_headers().image())?;
+
+        Ok(pe)
+    }
+
+    pub fn pe32_file(&self) -> pelite::Result<pe32::PeFile<'_>> {
+        use pelite::pe32::Pe;
+
+        check_header_alignment(&self.file_data, 4)?;
+        let pe = pe32::PeFile::from_bytes(&self.file_data)?;
+        check_section_alignment(pe.section_headers().image())?;
+
+        Ok(pe)
+    }
+
+    pub fn is_pe32(&self) -> bool {
+        self.pe32_file().is_ok()
+    }
+
+    /// Hex-encoded SHA-256 of the library file, for clients to detect rebuilds.
+    pub fn sha256(&self) -> String {
+        Sha256::digest(&self.file_data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
 }
 
 impl Default for Library {
@@ -31,6 +83,40 @@ impl Default for Library {
     }
 }
 
+/// pelite reads the NT and section headers in place, misaligned offsets must be refused beforehand.
+fn check_header_alignment(file_data: &[u8], nt_headers_align: usize) -> pelite::Result<()> {
+    let read_u32 = |offset: usize| -> pelite::Result<usize> {
+        let bytes = file_data
+            .get(offset..)
+            .and_then(|slice| slice.first_chunk())
+            .ok_or(pelite::Error::Bounds)?;
+        Ok(u32::from_le_bytes(*bytes) as usize)
+    };
+
+    let e_lfanew = read_u32(0x3C)?;
+    // FileHeader.SizeOfOptionalHeader, the section headers follow the optional header.
+    let size_of_optional_header = read_u32(e_lfanew.saturating_add(0x14))? & 0xFFFF;
+    let section_headers = e_lfanew + 0x18 + size_of_optional_header;
+
+    if !e_lfanew.is_multiple_of(nt_headers_align) || !section_headers.is_multiple_of(4) {
+        return Err(pelite::Error::Misaligned);
+    }
+
+    Ok(())
+}
+
+/// pelite checks the alignment of RVAs but reads from the raw offset, both have to agree.
+fn check_section_alignment(sections: &[IMAGE_SECTION_HEADER]) -> pelite::Result<()> {
+    if sections
+        .iter()
+        .any(|section| section.PointerToRawData % 8 != section.VirtualAddress % 8)
+    {
+        return Err(pelite::Error::Misaligned);
+    }
+
+    Ok(())
+}
+
 pub fn is_valid_library_id(id: &str) -> bool {
     !id.is_empty()
         && !id.starts_with('.')
@@ -41,3 +127,72 @@ pub fn is_valid_library_id(id: &str) -> bool {
             .chars()
             .all(|c| c.is_ascii_alphanumeric() || c.is_numeric() || c == '.' || c == '-')
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PeBuilder, Section};
+
+    fn library() -> Library {
+        PeBuilder::new()
+            .section(Section::new(".text", 0x1000, vec![0xC3; 0x10]))
+            .library()
+    }
+
+    #[test]
+    fn parses_aligned_headers() {
+        assert!(library().pe_file().is_ok());
+        assert!(!library().is_pe32());
+    }
+
+    #[test]
+    fn refuses_misaligned_nt_headers() {
+        let mut library = library();
+
+        // Move the NT headers by 4 bytes, which pelite would read in place.
+        library.file_data.splice(0x40..0x40, [0; 4]);
+        library.file_data[0x3C..0x40].copy_from_slice(&0x44u32.to_le_bytes());
+
+        assert!(matches!(library.pe_file(), Err(pelite::Error::Misaligned)));
+    }
+
+    #[test]
+    fn refuses_misaligned_section_headers() {
+        let mut library = library();
+
+        // FileHeader.SizeOfOptionalHeader moves the section headers off a 4-byte boundary.
+        library.file_data[0x54..0x56].copy_from_slice(&0xF2u16.to_le_bytes());
+
+        assert!(matches!(library.pe_file(), Err(pelite::Error::Misaligned)));
+    }
+
+    #[test]
+    fn refuses_raw_data_misaligned_with_its_rva() {
+        let library = PeBuilder::new()
+            .section(Section {
+                pointer_to_raw_data: Some(0x404),
+                ..Section::new(".text", 0x1000, vec![0xC3; 0x10])
+            })
+            .library();
+
+        assert!(matches!(library.pe_file(), Err(pelite::Error::Misaligned)));
+    }
+
+    #[test]
+    fn validates_library_ids() {
+        assert!(is_valid_library_id("example.dll"));
+        assert!(is_valid_library_id("example-1.0.dll"));
+
+        for id in [
+            "",
+            ".env",
+            "../example.dll",
+            "a/b.dll",
+            "a\\b.dll",
+            "a..dll",
+            "é.dll",
+        ] {
+            assert!(!is_valid_library_id(id), "{id}");
+        }
+    }
+}
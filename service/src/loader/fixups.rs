@@ -0,0 +1,52 @@
+use crate::{
+    error::{Error, Result},
+    loader::get_relocations,
+};
+use net_syringe_common::fixups::{FixupTable, ImportFixup, ImportName};
+use pelite::pe64::{Pe, PeFile, imports::Import};
+
+/// Collects the relocations and imports of an image that is mapped without an allocation base,
+/// for the client to apply once it has allocated the image.
+pub fn get_fixup_table(pe: &PeFile) -> Result<FixupTable> {
+    let relocations = get_relocations(pe)?;
+
+    let mut imports = Vec::new();
+
+    match pe.imports() {
+        Ok(descs) => {
+            for desc in descs {
+                let module = desc.dll_name()?.to_string();
+
+                for (idx, imp) in desc.int()?.enumerate() {
+                    let import = match imp? {
+                        Import::ByName { hint, name } => ImportName::Name {
+                            hint: hint as u16,
+                            name: name.to_string(),
+                        },
+                        Import::ByOrdinal { ord } => ImportName::Ordinal(ord),
+                    };
+
+                    let thunk_rva = u32::try_from(idx * 8)
+                        .ok()
+                        .and_then(|offset| desc.image().FirstThunk.checked_add(offset))
+                        .ok_or(Error::OutOfRange)?;
+
+                    imports.push(ImportFixup {
+                        thunk_rva,
+                        module: module.clone(),
+                        import,
+                    });
+                }
+            }
+        }
+        Err(pelite::Error::Null) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(FixupTable {
+        image_base: pe.optional_header().ImageBase,
+        relocs_stripped: relocations.is_none(),
+        relocations: relocations.unwrap_or_default(),
+        imports,
+    })
+}
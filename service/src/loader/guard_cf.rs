@@ -0,0 +1,95 @@
+//! Control Flow Guard function pointers the OS loader fills in for images built with `/guard:cf`.
+
+use crate::{
+    dto,
+    error::{Error, Result},
+};
+use pelite::{
+    image::{
+        IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_GUARD_CF_INSTRUMENTED, IMAGE_GUARDCF64,
+        IMAGE_LOAD_CONFIG_DIRECTORY64,
+    },
+    pe64::{Pe, PeFile},
+};
+use std::mem::{offset_of, size_of};
+
+/// RVAs of the guard function pointers in the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardCfSlots {
+    pub check_function: Option<u32>,
+    pub dispatch_function: Option<u32>,
+}
+
+/// Returns the guard function pointer slots of an image instrumented for Control Flow Guard.
+pub fn get_guard_cf_slots(pe: &PeFile) -> Result<Option<GuardCfSlots>> {
+    let load_config = match pe.load_config() {
+        Ok(load_config) => load_config.image(),
+        Err(pelite::Error::Null) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    // The guard fields follow the base load config, older layouts end before them.
+    let guard_flags_end =
+        size_of::<IMAGE_LOAD_CONFIG_DIRECTORY64>() + offset_of!(IMAGE_GUARDCF64, GuardFlags) + 4;
+    if (load_config.Size as usize) < guard_flags_end {
+        return Ok(None);
+    }
+
+    let load_config_rva = pe.data_directory()[IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG].VirtualAddress;
+    let guard_cf: &IMAGE_GUARDCF64 = pe.derva(
+        load_config_rva
+            .checked_add(size_of::<IMAGE_LOAD_CONFIG_DIRECTORY64>() as u32)
+            .ok_or(Error::OutOfRange)?,
+    )?;
+
+    if guard_cf.GuardFlags & IMAGE_GUARD_CF_INSTRUMENTED == 0 {
+        return Ok(None);
+    }
+
+    let slot_rva = |va: u64| -> Result<Option<u32>> {
+        if va == 0 {
+            return Ok(None);
+        }
+        Ok(Some(pe.va_to_rva(va)?))
+    };
+
+    Ok(Some(GuardCfSlots {
+        check_function: slot_rva(guard_cf.GuardCFCheckFunctionPointer)?,
+        dispatch_function: slot_rva(guard_cf.GuardCFDispatchFunctionPointer)?,
+    }))
+}
+
+/// Stores the supplied guard functions in the image and returns the RVAs of the patched slots.
+/// Refuses images instrumented for Control Flow Guard when no functions are supplied.
+pub fn patch_guard_cf(
+    pe: &PeFile,
+    mapped_image: &mut [u8],
+    functions: Option<&dto::GuardCfFunctions>,
+) -> Result<Vec<u32>> {
+    let Some(slots) = get_guard_cf_slots(pe)? else {
+        return Ok(Vec::new());
+    };
+
+    let functions = functions.ok_or(Error::ControlFlowGuard)?;
+
+    let mut patched = Vec::new();
+
+    for (rva, address) in [
+        (slots.check_function, functions.check_function),
+        (slots.dispatch_function, functions.dispatch_function),
+    ] {
+        let Some(rva) = rva else {
+            continue;
+        };
+
+        let slot: &mut [u8; 8] = mapped_image
+            .get_mut(rva as usize..)
+            .and_then(|slice| slice.first_chunk_mut())
+            .ok_or(Error::OutOfRange)?;
+
+        *slot = address.to_le_bytes();
+        patched.push(rva);
+    }
+
+    Ok(patched)
+}
@@ -2,42 +2,101 @@ use crate::{
     dto,
     error::{Error, MissingImportError, Result},
 };
-use pelite::pe::{Pe, PeFile, imports::Import};
-use std::collections::HashMap;
+use net_syringe_common::fixups::normalize_module_name;
+use pelite::pe64::{Pe, PeFile, imports::Import};
+use std::collections::{HashMap, HashSet};
 
+/// Identifies an import regardless of the descriptor it appears in.
+#[derive(PartialEq, Eq, Hash)]
+enum ImportKey {
+    Name(String),
+    Ordinal(u16),
+}
+
+/// Returns the imports of every library, merging libraries imported by several descriptors or
+/// in different cases.
 pub fn get_library_imports(pe: &PeFile) -> pelite::Result<HashMap<String, Vec<dto::Import>>> {
     let mut imports: HashMap<String, Vec<dto::Import>> = HashMap::new();
+    let mut seen: HashSet<(String, ImportKey)> = HashSet::new();
+    // First spelling of every module, the same module may be imported in different cases.
+    let mut dll_names: HashMap<String, String> = HashMap::new();
 
     for desc in pe.imports()? {
-        let dll_name = desc.dll_name()?;
+        let dll_name = desc.dll_name()?.to_string();
+        let module_key = normalize_module_name(&dll_name);
+        let dll_name = dll_names.entry(module_key.clone()).or_insert(dll_name);
 
-        let mut dll_imports: Vec<dto::Import> = Vec::new();
+        let dll_imports = imports.entry(dll_name.clone()).or_default();
 
         for imp in desc.int()? {
-            match imp? {
-                Import::ByName { hint, name } => dll_imports.push(dto::Import::ByName {
-                    hint: hint,
-                    name: name.to_string(),
-                    address: None,
-                }),
-                Import::ByOrdinal { ord } => dll_imports.push(dto::Import::ByOrdinal {
-                    ordinal: ord,
-                    address: None,
-                }),
+            let (key, import) = match imp? {
+                Import::ByName { hint, name } => (
+                    ImportKey::Name(name.to_string()),
+                    dto::Import::ByName {
+                        hint,
+                        name: name.to_string(),
+                        address: None,
+                    },
+                ),
+                Import::ByOrdinal { ord } => (
+                    ImportKey::Ordinal(ord),
+                    dto::Import::ByOrdinal {
+                        ordinal: ord,
+                        address: None,
+                    },
+                ),
+            };
+
+            if seen.insert((module_key.clone(), key)) {
+                dll_imports.push(import);
             }
         }
-
-        imports.insert(dll_name.to_string(), dll_imports);
     }
 
     Ok(imports)
 }
 
-fn as_u64_mut(slice: &mut [u8]) -> Option<&mut u64> {
-    if slice.len() >= 8 && slice.as_ptr().align_offset(std::mem::align_of::<u64>()) == 0 {
-        Some(unsafe { &mut *(slice.as_mut_ptr() as *mut u64) })
-    } else {
-        None
+/// Import addresses supplied by the client for one library, indexed by name and ordinal.
+#[derive(Debug, Default)]
+pub struct ImportIndex<'a> {
+    by_name: HashMap<&'a str, u64>,
+    by_ordinal: HashMap<u16, u64>,
+}
+
+impl<'a> ImportIndex<'a> {
+    pub fn new(imports: &'a [dto::Import]) -> Self {
+        let mut index = Self::default();
+        index.extend(imports);
+        index
+    }
+
+    pub fn extend(&mut self, imports: &'a [dto::Import]) {
+        for import in imports {
+            match import {
+                dto::Import::ByName {
+                    name,
+                    address: Some(address),
+                    ..
+                } => {
+                    self.by_name.insert(name, *address);
+                }
+                dto::Import::ByOrdinal {
+                    ordinal,
+                    address: Some(address),
+                } => {
+                    self.by_ordinal.insert(*ordinal, *address);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<u64> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn by_ordinal(&self, ordinal: u16) -> Option<u64> {
+        self.by_ordinal.get(&ordinal).copied()
     }
 }
 
@@ -52,60 +111,221 @@ pub fn resolve_imports(
         Err(err) => return Err(err.into()),
     };
 
+    let mut indexes: HashMap<String, ImportIndex> = HashMap::new();
+    for (dll_name, imports) in exports {
+        indexes
+            .entry(normalize_module_name(dll_name))
+            .or_default()
+            .extend(imports);
+    }
+
     for desc in imports {
         let dll_name = desc.dll_name()?.to_string();
 
-        let exports = exports
-            .get(&dll_name)
+        let index = indexes
+            .get(&normalize_module_name(&dll_name))
             .ok_or(Error::MissingImport(MissingImportError::Library(dll_name)))?;
 
         for (idx, imp) in desc.int()?.enumerate() {
-            let first_thunk_rva = desc.image().FirstThunk as usize + idx * 8;
-            let address = as_u64_mut(
-                mapped_image
-                    .get_mut(first_thunk_rva..first_thunk_rva + 8)
-                    .ok_or(Error::PeError(pelite::Error::Overflow))?,
-            )
-            .ok_or(pelite::Error::Misaligned)?;
-
-            match imp? {
-                Import::ByName { name, .. } => {
-                    *address = exports
-                        .iter()
-                        .find_map(|imp| match imp {
-                            dto::Import::ByName {
-                                name: imp_name,
-                                address,
-                                ..
-                            } => {
-                                if name == imp_name {
-                                    return *address;
-                                }
-                                None
-                            }
-                            _ => None,
-                        })
-                        .ok_or(Error::MissingImport(MissingImportError::Name(
-                            name.to_string(),
-                        )))?;
-                }
-                Import::ByOrdinal { ord } => {
-                    *address = exports
-                        .iter()
-                        .find_map(|imp| match imp {
-                            dto::Import::ByOrdinal { ordinal, address } => {
-                                if ord == *ordinal {
-                                    return *address;
-                                }
-                                None
-                            }
-                            _ => None,
-                        })
-                        .ok_or(Error::MissingImport(MissingImportError::Ordinal(ord)))?;
-                }
-            }
+            let thunk_rva = (desc.image().FirstThunk as usize)
+                .checked_add(idx * 8)
+                .ok_or(Error::OutOfRange)?;
+
+            let thunk: &mut [u8; 8] = mapped_image
+                .get_mut(thunk_rva..)
+                .and_then(|slice| slice.first_chunk_mut())
+                .ok_or(Error::OutOfRange)?;
+
+            let address = match imp? {
+                Import::ByName { name, .. } => name
+                    .to_str()
+                    .ok()
+                    .and_then(|name| index.by_name(name))
+                    .ok_or(Error::MissingImport(MissingImportError::Name(
+                        name.to_string(),
+                    )))?,
+                Import::ByOrdinal { ord } => index
+                    .by_ordinal(ord)
+                    .ok_or(Error::MissingImport(MissingImportError::Ordinal(ord)))?,
+            };
+
+            *thunk = address.to_le_bytes();
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loader::map_image,
+        testing::{PeBuilder, Section},
+    };
+
+    fn by_name(name: &str, address: Option<u64>) -> dto::Import {
+        dto::Import::ByName {
+            hint: 0,
+            name: name.to_string(),
+            address,
+        }
+    }
+
+    /// Address the client supplies for an import, derived from its name.
+    fn address_of(name: &str) -> u64 {
+        0x7FF0_0000_0000 + name.bytes().map(u64::from).sum::<u64>() * 0x10
+    }
+
+    fn client_imports(
+        imports: &HashMap<String, Vec<dto::Import>>,
+    ) -> HashMap<String, Vec<dto::Import>> {
+        imports
+            .iter()
+            .map(|(dll_name, imports)| {
+                let imports = imports
+                    .iter()
+                    .map(|import| match import {
+                        dto::Import::ByName { name, .. } => by_name(name, Some(address_of(name))),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                (dll_name.clone(), imports)
+            })
+            .collect()
+    }
+
+    /// Checks every import address table slot of every descriptor holds its import's address.
+    fn assert_resolved(pe: &PeFile, mapped_image: &[u8]) {
+        for desc in pe.imports().unwrap() {
+            for (idx, imp) in desc.int().unwrap().enumerate() {
+                let Import::ByName { name, .. } = imp.unwrap() else {
+                    unreachable!();
+                };
+                let thunk_rva = desc.image().FirstThunk as usize + idx * 8;
+                let address =
+                    u64::from_le_bytes(mapped_image[thunk_rva..thunk_rva + 8].try_into().unwrap());
+
+                assert_eq!(address, address_of(name.to_str().unwrap()), "{name}");
+            }
+        }
+    }
+
+    fn library() -> PeBuilder {
+        PeBuilder::new().section(Section::new(".text", 0x1000, vec![0xC3; 0x10]))
+    }
+
+    #[test]
+    fn merges_descriptors_of_the_same_library() {
+        let library = library()
+            .import("user32.dll", &["MessageBoxA", "GetDC"])
+            .import("gdi32.dll", &["BitBlt"])
+            .import("user32.dll", &["GetDC", "ReleaseDC"])
+            .library();
+        let pe = library.pe_file().unwrap();
+
+        let imports = get_library_imports(&pe).unwrap();
+
+        assert_eq!(
+            imports,
+            HashMap::from([
+                (
+                    "user32.dll".to_string(),
+                    vec![
+                        by_name("MessageBoxA", None),
+                        by_name("GetDC", None),
+                        by_name("ReleaseDC", None),
+                    ]
+                ),
+                ("gdi32.dll".to_string(), vec![by_name("BitBlt", None)]),
+            ])
+        );
+
+        // The import listed once is still filled in for both descriptors.
+        let mut mapped_image = map_image(&pe, &library).unwrap();
+        resolve_imports(&pe, &mut mapped_image, &client_imports(&imports)).unwrap();
+        assert_resolved(&pe, &mapped_image);
+    }
+
+    #[test]
+    fn reports_missing_imports() {
+        let library = library()
+            .import("user32.dll", &["MessageBoxA", "GetDC"])
+            .library();
+        let pe = library.pe_file().unwrap();
+        let mut mapped_image = map_image(&pe, &library).unwrap();
+
+        let exports = HashMap::from([(
+            "user32.dll".to_string(),
+            vec![by_name("MessageBoxA", Some(1))],
+        )]);
+        assert!(matches!(
+            resolve_imports(&pe, &mut mapped_image, &exports),
+            Err(Error::MissingImport(MissingImportError::Name(name))) if name == "GetDC"
+        ));
+
+        assert!(matches!(
+            resolve_imports(&pe, &mut mapped_image, &HashMap::new()),
+            Err(Error::MissingImport(MissingImportError::Library(name))) if name == "user32.dll"
+        ));
+    }
+
+    #[test]
+    fn merges_libraries_imported_in_different_cases() {
+        let library = library()
+            .import("KERNEL32.dll", &["GetCurrentProcessId", "Sleep"])
+            .import("kernel32.dll", &["Sleep", "ExitProcess"])
+            .library();
+        let pe = library.pe_file().unwrap();
+
+        // The first spelling is reported.
+        let imports = get_library_imports(&pe).unwrap();
+        assert_eq!(
+            imports,
+            HashMap::from([(
+                "KERNEL32.dll".to_string(),
+                vec![
+                    by_name("GetCurrentProcessId", None),
+                    by_name("Sleep", None),
+                    by_name("ExitProcess", None),
+                ]
+            )])
+        );
+
+        // The client may answer in a spelling of its own.
+        let mut exports = client_imports(&imports);
+        let kernel32 = exports.remove("KERNEL32.dll").unwrap();
+        exports.insert("Kernel32.DLL".to_string(), kernel32);
+
+        let mut mapped_image = map_image(&pe, &library).unwrap();
+        resolve_imports(&pe, &mut mapped_image, &exports).unwrap();
+        assert_resolved(&pe, &mapped_image);
+    }
+
+    #[test]
+    fn resolves_imports_split_across_spellings() {
+        let library = library()
+            .import("KERNEL32.dll", &["GetCurrentProcessId"])
+            .import("kernel32.dll", &["Sleep"])
+            .library();
+        let pe = library.pe_file().unwrap();
+
+        let exports = HashMap::from([
+            (
+                "kernel32.DLL".to_string(),
+                vec![by_name("Sleep", Some(address_of("Sleep")))],
+            ),
+            (
+                "Kernel32.dll".to_string(),
+                vec![by_name(
+                    "GetCurrentProcessId",
+                    Some(address_of("GetCurrentProcessId")),
+                )],
+            ),
+        ]);
+
+        let mut mapped_image = map_image(&pe, &library).unwrap();
+        resolve_imports(&pe, &mut mapped_image, &exports).unwrap();
+        assert_resolved(&pe, &mapped_image);
+    }
+}
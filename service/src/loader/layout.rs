@@ -0,0 +1,265 @@
+//! Section placement rules of the Windows image loader.
+
+use crate::error::{Error, Result};
+use pelite::image::IMAGE_SECTION_HEADER;
+
+pub const PAGE_SIZE: u32 = 0x1000;
+
+/// The loader rounds `PointerToRawData` down to this boundary regardless of `FileAlignment`.
+const RAW_DATA_ALIGNMENT: u32 = 0x200;
+
+/// Largest `SizeOfImage` the service allocates a mapping for.
+pub const MAX_IMAGE_SIZE: u32 = 0x4000_0000;
+
+/// Where a section's raw data is copied from and to, and how many bytes are copied.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SectionLayout {
+    pub raw_offset: usize,
+    pub virtual_address: usize,
+    pub size: usize,
+}
+
+fn align_up(value: u32, alignment: u32) -> u64 {
+    (value as u64).div_ceil(alignment as u64) * alignment as u64
+}
+
+fn align_down(value: u32, alignment: u32) -> u32 {
+    value & !(alignment - 1)
+}
+
+/// Images with a section alignment below the page size are mapped as a flat copy of the file.
+pub fn is_low_alignment(section_alignment: u32) -> bool {
+    section_alignment < PAGE_SIZE
+}
+
+pub fn validate_alignment(file_alignment: u32, section_alignment: u32) -> Result<()> {
+    if !file_alignment.is_power_of_two() || !section_alignment.is_power_of_two() {
+        return Err(Error::InvalidAlignment);
+    }
+
+    let valid = if is_low_alignment(section_alignment) {
+        file_alignment == section_alignment
+    } else {
+        file_alignment <= section_alignment
+    };
+
+    if !valid {
+        return Err(Error::InvalidAlignment);
+    }
+
+    Ok(())
+}
+
+/// Size of the section in memory. The loader uses the raw size for sections without a virtual
+/// size.
+pub fn virtual_size(section: &IMAGE_SECTION_HEADER) -> u32 {
+    match section.VirtualSize {
+        0 => section.SizeOfRawData,
+        size => size,
+    }
+}
+
+pub fn section_layout(
+    section: &IMAGE_SECTION_HEADER,
+    file_alignment: u32,
+    section_alignment: u32,
+    file_size: usize,
+) -> Result<SectionLayout> {
+    if !section.VirtualAddress.is_multiple_of(section_alignment) {
+        return Err(Error::InvalidAlignment);
+    }
+
+    let virtual_address = section.VirtualAddress as usize;
+
+    // Uninitialized data has nothing to copy, the mapping is zero-filled.
+    if section.PointerToRawData == 0 || section.SizeOfRawData == 0 {
+        return Ok(SectionLayout {
+            raw_offset: 0,
+            virtual_address,
+            size: 0,
+        });
+    }
+
+    let raw_offset = align_down(section.PointerToRawData, RAW_DATA_ALIGNMENT) as usize;
+    let raw_size = align_up(section.SizeOfRawData, file_alignment);
+
+    let virtual_size = virtual_size(section);
+
+    // Raw data past the virtual size is dropped, raw data past the end of the file is not read.
+    let size = raw_size
+        .min(align_up(virtual_size, section_alignment))
+        .min(file_size.saturating_sub(raw_offset) as u64) as usize;
+
+    Ok(SectionLayout {
+        raw_offset,
+        virtual_address,
+        size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(
+        virtual_address: u32,
+        virtual_size: u32,
+        pointer_to_raw_data: u32,
+        size_of_raw_data: u32,
+    ) -> IMAGE_SECTION_HEADER {
+        IMAGE_SECTION_HEADER {
+            Name: *b".test\0\0\0",
+            VirtualSize: virtual_size,
+            VirtualAddress: virtual_address,
+            SizeOfRawData: size_of_raw_data,
+            PointerToRawData: pointer_to_raw_data,
+            PointerToRelocations: 0,
+            PointerToLinenumbers: 0,
+            NumberOfRelocations: 0,
+            NumberOfLinenumbers: 0,
+            Characteristics: 0x4000_0040,
+        }
+    }
+
+    fn layout(raw_offset: usize, virtual_address: usize, size: usize) -> SectionLayout {
+        SectionLayout {
+            raw_offset,
+            virtual_address,
+            size,
+        }
+    }
+
+    #[test]
+    fn copies_the_raw_data() {
+        // .text of the MSVC fixture.
+        let section = section(0x1000, 0x11BB, 0x400, 0x1200);
+
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x7200).unwrap(),
+            layout(0x400, 0x1000, 0x1200)
+        );
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_file() {
+        let section = section(0x1000, 0x2000, 0x400, 0x1000);
+
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x800).unwrap(),
+            layout(0x400, 0x1000, 0x400)
+        );
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x200).unwrap(),
+            layout(0x400, 0x1000, 0)
+        );
+    }
+
+    #[test]
+    fn drops_raw_data_past_the_virtual_size() {
+        // Only whole pages past the virtual size are dropped.
+        let section = section(0x1000, 0x10, 0x400, 0x2000);
+
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x10000).unwrap(),
+            layout(0x400, 0x1000, 0x1000)
+        );
+    }
+
+    #[test]
+    fn uses_the_raw_size_without_a_virtual_size() {
+        let section = section(0x1000, 0, 0x400, 0x1800);
+
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x10000).unwrap(),
+            layout(0x400, 0x1000, 0x1800)
+        );
+    }
+
+    #[test]
+    fn rounds_the_raw_size_up_to_the_file_alignment() {
+        let section = section(0x1000, 0x1000, 0x400, 0x150);
+
+        assert_eq!(
+            section_layout(&section, 0x200, 0x1000, 0x10000).unwrap(),
+            layout(0x400, 0x1000, 0x200)
+        );
+    }
+
+    #[test]
+    fn rounds_the_raw_offset_down_to_0x200() {
+        let file_size = 0x10000;
+
+        assert_eq!(
+            section_layout(
+                &section(0x1000, 0x200, 0x5FF, 0x200),
+                0x200,
+                0x1000,
+                file_size
+            )
+            .unwrap()
+            .raw_offset,
+            0x400
+        );
+        assert_eq!(
+            section_layout(
+                &section(0x1000, 0x200, 0x600, 0x200),
+                0x200,
+                0x1000,
+                file_size
+            )
+            .unwrap()
+            .raw_offset,
+            0x600
+        );
+        // Regardless of a larger file alignment.
+        assert_eq!(
+            section_layout(
+                &section(0x1000, 0x200, 0x1200, 0x200),
+                0x1000,
+                0x1000,
+                file_size
+            )
+            .unwrap()
+            .raw_offset,
+            0x1200
+        );
+    }
+
+    #[test]
+    fn skips_uninitialized_data() {
+        for section in [
+            section(0x1000, 0x1000, 0, 0x200),
+            section(0x1000, 0x1000, 0x400, 0),
+        ] {
+            assert_eq!(
+                section_layout(&section, 0x200, 0x1000, 0x10000).unwrap(),
+                layout(0, 0x1000, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_misaligned_sections() {
+        assert!(matches!(
+            section_layout(
+                &section(0x1200, 0x200, 0x400, 0x200),
+                0x200,
+                0x1000,
+                0x10000
+            ),
+            Err(Error::InvalidAlignment)
+        ));
+    }
+
+    #[test]
+    fn validates_alignment() {
+        assert!(validate_alignment(0x200, 0x1000).is_ok());
+        assert!(validate_alignment(0x1000, 0x1000).is_ok());
+        assert!(validate_alignment(0x200, 0x200).is_ok());
+
+        assert!(validate_alignment(0x2000, 0x1000).is_err());
+        assert!(validate_alignment(0x200, 0x400).is_err());
+        assert!(validate_alignment(0x300, 0x1000).is_err());
+        assert!(validate_alignment(0x200, 0).is_err());
+    }
+}
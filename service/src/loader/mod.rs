@@ -1,46 +1,79 @@
 use crate::{
+    dto::{self, Feature},
     error::{Error, Result},
     library::Library,
 };
+pub use fixups::*;
+pub use guard_cf::*;
 pub use imports::*;
-use pelite::pe::{Pe, PeFile};
+pub use layout::*;
+use net_syringe_common::envelope::DiscardableSection;
+use pelite::{
+    image::{
+        IMAGE_DIRECTORY_ENTRY_EXCEPTION, IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_EXPORT_DIRECTORY,
+        IMAGE_SCN_MEM_DISCARDABLE, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE,
+    },
+    pe64::{Pe, PeFile},
+};
 pub use relocations::*;
 pub use security_cookie::*;
 
+mod fixups;
+mod guard_cf;
 mod imports;
+mod layout;
 mod relocations;
 mod security_cookie;
 
 pub fn map_image(pe: &PeFile, library: &Library) -> Result<Vec<u8>> {
-    let mut mapped_data = Vec::new();
-    mapped_data.resize(pe.optional_header().SizeOfImage as usize, 0);
+    let optional_header = pe.optional_header();
+    let file_alignment = optional_header.FileAlignment;
+    let section_alignment = optional_header.SectionAlignment;
+
+    validate_alignment(file_alignment, section_alignment)?;
+
+    if optional_header.SizeOfImage > MAX_IMAGE_SIZE {
+        return Err(Error::ImageTooLarge(optional_header.SizeOfImage));
+    }
+
+    let file_data = &library.file_data;
+    let mut mapped_data = vec![0; optional_header.SizeOfImage as usize];
+
+    if is_low_alignment(section_alignment) {
+        // File offsets equal RVAs, the whole file is mapped including the headers.
+        let size = file_data.len().min(mapped_data.len());
+        mapped_data[..size].copy_from_slice(&file_data[..size]);
+
+        if !library.map_pe_headers {
+            let size_of_headers = (optional_header.SizeOfHeaders as usize).min(size);
+            mapped_data[..size_of_headers].fill(0);
+        }
+
+        return Ok(mapped_data);
+    }
 
     if library.map_pe_headers {
-        let size_of_headers = pe.optional_header().SizeOfHeaders as usize;
+        let size_of_headers = (optional_header.SizeOfHeaders as usize).min(file_data.len());
 
         mapped_data
             .get_mut(0..size_of_headers)
             .ok_or(Error::OutOfRange)?
-            .copy_from_slice(
-                &library
-                    .file_data
-                    .get(0..size_of_headers)
-                    .ok_or(Error::OutOfRange)?,
-            );
+            .copy_from_slice(&file_data[0..size_of_headers]);
     }
 
     for section in pe.section_headers() {
-        let raw_data_offset = section.PointerToRawData as usize;
-        let virtual_data_offset = section.VirtualAddress as usize;
-        let raw_data_size = section.SizeOfRawData as usize;
+        let layout = section_layout(section, file_alignment, section_alignment, file_data.len())?;
+
+        if layout.size == 0 {
+            continue;
+        }
 
-        let src = library
-            .file_data
-            .get(raw_data_offset..raw_data_offset + raw_data_size)
+        let src = file_data
+            .get(layout.raw_offset..layout.raw_offset + layout.size)
             .ok_or(Error::OutOfRange)?;
 
         let dst = mapped_data
-            .get_mut(virtual_data_offset..virtual_data_offset + raw_data_size)
+            .get_mut(layout.virtual_address..layout.virtual_address + layout.size)
             .ok_or(Error::OutOfRange)?;
 
         dst.copy_from_slice(src);
@@ -48,3 +81,350 @@ pub fn map_image(pe: &PeFile, library: &Library) -> Result<Vec<u8>> {
 
     Ok(mapped_data)
 }
+
+pub fn get_regions(pe: &PeFile) -> Vec<dto::Region> {
+    pe.section_headers()
+        .iter()
+        .map(|sect| dto::Region {
+            rva: sect.VirtualAddress,
+            size: virtual_size(sect),
+            characteristics: sect.Characteristics
+                & (IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE | IMAGE_SCN_MEM_EXECUTE),
+        })
+        .collect()
+}
+
+/// Returns the sections marked `IMAGE_SCN_MEM_DISCARDABLE`, such as `.reloc`.
+pub fn get_discardable_sections(pe: &PeFile) -> Vec<DiscardableSection> {
+    pe.section_headers()
+        .iter()
+        .filter(|sect| sect.Characteristics & IMAGE_SCN_MEM_DISCARDABLE != 0)
+        .map(|sect| DiscardableSection {
+            name: match sect.name() {
+                Ok(name) => name.to_string(),
+                Err(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            },
+            rva: sect.VirtualAddress,
+            size: virtual_size(sect),
+        })
+        .collect()
+}
+
+/// Returns the RVAs of the image's TLS callbacks.
+pub fn get_tls_callbacks(pe: &PeFile) -> pelite::Result<Vec<u32>> {
+    let tls = match pe.tls() {
+        Ok(tls) => tls,
+        Err(pelite::Error::Null) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    tls.callbacks()?
+        .iter()
+        .map(|va| pe.va_to_rva(*va))
+        .collect()
+}
+
+/// Returns the RVA of the image's entry point, `None` for libraries without one such as
+/// resource-only DLLs.
+pub fn get_entry_point(pe: &PeFile) -> Option<u32> {
+    match pe.optional_header().AddressOfEntryPoint {
+        0 => None,
+        rva => Some(rva),
+    }
+}
+
+/// Reads the `index`th little-endian integer of `N` bytes from `table`.
+fn table_entry<const N: usize>(table: &[u8], index: usize) -> pelite::Result<[u8; N]> {
+    table
+        .get(index * N..(index + 1) * N)
+        .and_then(|entry| entry.try_into().ok())
+        .ok_or(pelite::Error::Bounds)
+}
+
+/// Returns the RVA of the function exported as `name`. Forwarded exports are not part of the
+/// image and are not returned.
+///
+/// The export tables are read as bytes, since lld-link only aligns the name and ordinal tables
+/// to a byte, which the Windows loader accepts and pelite's typed accessors refuse.
+pub fn get_export(pe: &PeFile, name: &str) -> pelite::Result<Option<u32>> {
+    let dir = match pe.data_directory().get(IMAGE_DIRECTORY_ENTRY_EXPORT) {
+        Some(dir) if dir.VirtualAddress != 0 => dir,
+        _ => return Ok(None),
+    };
+    let exports: &IMAGE_EXPORT_DIRECTORY = pe.derva(dir.VirtualAddress)?;
+
+    let count = exports.NumberOfNames as usize;
+    let names = pe.derva_slice::<u8>(exports.AddressOfNames, count * 4)?;
+    let ordinals = pe.derva_slice::<u8>(exports.AddressOfNameOrdinals, count * 2)?;
+
+    for idx in 0..count {
+        let name_rva = u32::from_le_bytes(table_entry(names, idx)?);
+        if *pe.derva_c_str(name_rva)? != *name {
+            continue;
+        }
+
+        let ordinal = u16::from_le_bytes(table_entry(ordinals, idx)?) as usize;
+        let functions = pe.derva_slice::<u8>(
+            exports.AddressOfFunctions,
+            exports.NumberOfFunctions as usize * 4,
+        )?;
+        let rva = u32::from_le_bytes(table_entry(functions, ordinal)?);
+
+        // Forwarders are strings within the export directory.
+        let is_forwarded = (dir.VirtualAddress..dir.VirtualAddress + dir.Size).contains(&rva);
+
+        return Ok((rva != 0 && !is_forwarded).then_some(rva));
+    }
+
+    Ok(None)
+}
+
+/// Returns the RVA and size of the image's exception table.
+pub fn get_exception_table(pe: &PeFile) -> Option<(u32, u32)> {
+    let dir = pe.data_directory().get(IMAGE_DIRECTORY_ENTRY_EXCEPTION)?;
+    if dir.VirtualAddress == 0 || dir.Size == 0 {
+        return None;
+    }
+    Some((dir.VirtualAddress, dir.Size))
+}
+
+/// Returns the features a client must support for the library to run correctly.
+pub fn get_required_features(pe: &PeFile) -> pelite::Result<Vec<Feature>> {
+    let mut features = Vec::new();
+
+    if !get_tls_callbacks(pe)?.is_empty() {
+        features.push(Feature::TlsCallbacks);
+    }
+
+    Ok(features)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PeBuilder, Section, fixture};
+
+    /// `DllMain` of the fixtures built from `fixture.s`: `mov eax, 1; ret`.
+    const FIXTURE_ENTRY_POINT: [u8; 6] = [0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3];
+
+    fn layouts(library: &Library) -> Vec<(usize, usize, usize)> {
+        let pe = library.pe_file().unwrap();
+        let optional_header = pe.optional_header();
+
+        pe.section_headers()
+            .iter()
+            .map(|section| {
+                let layout = section_layout(
+                    section,
+                    optional_header.FileAlignment,
+                    optional_header.SectionAlignment,
+                    library.file_data.len(),
+                )
+                .unwrap();
+                (layout.raw_offset, layout.virtual_address, layout.size)
+            })
+            .collect()
+    }
+
+    /// Maps the library and checks every section landed where `layouts` says, and nothing else
+    /// was copied.
+    fn assert_mapped(library: &Library, layouts: &[(usize, usize, usize)]) -> Vec<u8> {
+        let pe = library.pe_file().unwrap();
+        let mapped = map_image(&pe, library).unwrap();
+        let mut expected = vec![0u8; pe.optional_header().SizeOfImage as usize];
+
+        for (raw_offset, virtual_address, size) in layouts {
+            expected[*virtual_address..virtual_address + size]
+                .copy_from_slice(&library.file_data[*raw_offset..raw_offset + size]);
+        }
+
+        assert!(mapped == expected, "mapped image differs");
+        mapped
+    }
+
+    #[test]
+    fn maps_msvc_image() {
+        let library = fixture("msvc.dll");
+        let expected = [
+            (0x400, 0x1000, 0x1200),
+            (0x1600, 0x3000, 0x1200),
+            (0x2800, 0x5000, 0x200),
+            (0x2A00, 0x6000, 0x200),
+            (0x2C00, 0x7000, 0x200),
+            (0x2E00, 0x8000, 0x4200),
+            (0x7000, 0xD000, 0x200),
+        ];
+
+        assert_eq!(layouts(&library), expected);
+        assert_mapped(&library, &expected);
+    }
+
+    #[test]
+    fn maps_lld_link_image() {
+        let library = fixture("lld-link.dll");
+        // .bss is merged into .data, which is larger than its raw data.
+        let expected = [
+            (0x400, 0x1000, 0x200),
+            (0x600, 0x2000, 0x200),
+            (0x800, 0x3000, 0x200),
+            (0xA00, 0x5000, 0x200),
+        ];
+
+        assert_eq!(layouts(&library), expected);
+        let mapped = assert_mapped(&library, &expected);
+        assert_eq!(mapped[0x1000..0x1006], FIXTURE_ENTRY_POINT);
+    }
+
+    #[test]
+    fn maps_mingw_image() {
+        let library = fixture("mingw.dll");
+        // .bss is a section of its own without raw data, exports and imports get .edata and
+        // .idata.
+        let expected = [
+            (0x400, 0x1000, 0x200),
+            (0x600, 0x2000, 0x200),
+            (0x800, 0x3000, 0x200),
+            (0, 0x4000, 0),
+            (0xA00, 0x6000, 0x200),
+            (0xC00, 0x7000, 0x200),
+            (0xE00, 0x8000, 0x200),
+        ];
+
+        assert_eq!(layouts(&library), expected);
+        let mapped = assert_mapped(&library, &expected);
+        assert_eq!(mapped[0x1000..0x1006], FIXTURE_ENTRY_POINT);
+    }
+
+    #[test]
+    fn maps_headers_on_request() {
+        let library = Library {
+            map_pe_headers: true,
+            ..fixture("lld-link.dll")
+        };
+        let pe = library.pe_file().unwrap();
+        let mapped = map_image(&pe, &library).unwrap();
+
+        assert_eq!(mapped[..0x400], library.file_data[..0x400]);
+    }
+
+    #[test]
+    fn maps_raw_data_cut_off_by_the_end_of_the_file() {
+        let library = PeBuilder::new()
+            .section(Section::new(".text", 0x1000, vec![0xC3; 0x200]))
+            .section(Section {
+                virtual_size: 0x2000,
+                size_of_raw_data: Some(0x1000),
+                ..Section::new(".data", 0x2000, vec![0xAA; 0x300])
+            })
+            .file_size(0x900)
+            .library();
+
+        assert_eq!(library.file_data.len(), 0x900);
+        assert_eq!(
+            layouts(&library),
+            [(0x400, 0x1000, 0x200), (0x600, 0x2000, 0x300)]
+        );
+        assert_mapped(&library, &[(0x400, 0x1000, 0x200), (0x600, 0x2000, 0x300)]);
+    }
+
+    #[test]
+    fn maps_sections_without_a_virtual_size() {
+        let library = PeBuilder::new()
+            .section(Section {
+                virtual_size: 0,
+                ..Section::new(".text", 0x1000, vec![0xC3; 0x1400])
+            })
+            .size_of_image(0x3000)
+            .library();
+
+        assert_eq!(layouts(&library), [(0x400, 0x1000, 0x1400)]);
+        assert_mapped(&library, &[(0x400, 0x1000, 0x1400)]);
+    }
+
+    #[test]
+    fn reports_sections_without_a_virtual_size_at_their_raw_size() {
+        let library = PeBuilder::new()
+            .section(Section {
+                virtual_size: 0,
+                ..Section::new(".text", 0x1000, vec![0xC3; 0x1400])
+            })
+            .section(Section {
+                virtual_size: 0,
+                characteristics: 0x4200_0040,
+                ..Section::new(".reloc", 0x3000, vec![0; 0x200])
+            })
+            .library();
+        let pe = library.pe_file().unwrap();
+
+        let regions = get_regions(&pe)
+            .into_iter()
+            .map(|region| (region.rva, region.size, region.characteristics))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                (0x1000, 0x1400, IMAGE_SCN_MEM_READ),
+                (0x3000, 0x200, IMAGE_SCN_MEM_READ)
+            ]
+        );
+
+        let discardable = get_discardable_sections(&pe)
+            .into_iter()
+            .map(|section| (section.name, section.rva, section.size))
+            .collect::<Vec<_>>();
+        assert_eq!(discardable, [(".reloc".to_string(), 0x3000, 0x200)]);
+    }
+
+    #[test]
+    fn maps_low_alignment_images_flat() {
+        let library = PeBuilder::new()
+            .alignment(0x200, 0x200)
+            .section(Section {
+                pointer_to_raw_data: Some(0x400),
+                ..Section::new(".text", 0x400, vec![0xC3; 0x200])
+            })
+            .section(Section {
+                pointer_to_raw_data: Some(0x600),
+                ..Section::new(".data", 0x600, vec![0xAA; 0x100])
+            })
+            .library();
+        let pe = library.pe_file().unwrap();
+        let mapped = map_image(&pe, &library).unwrap();
+
+        assert_eq!(mapped.len(), 0x800);
+        assert!(mapped[..0x400].iter().all(|byte| *byte == 0));
+        assert_eq!(mapped[0x400..0x700], library.file_data[0x400..0x700]);
+        assert!(mapped[0x700..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn finds_exports() {
+        for (name, export, rva) in [
+            // lld-link leaves the name and ordinal tables byte-aligned.
+            ("lld-link.dll", "Init", 0x1006),
+            ("mingw.dll", "Init", 0x1006),
+            ("msvc.dll", "ThrowException", 0x10C0),
+            ("msvc.dll", "?GLOBAL_A@@3VA@@A", 0x5018),
+        ] {
+            let library = fixture(name);
+            let pe = library.pe_file().unwrap();
+
+            assert_eq!(get_export(&pe, export).unwrap(), Some(rva), "{name}");
+            assert_eq!(get_export(&pe, "Missing").unwrap(), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn refuses_oversized_images() {
+        let library = PeBuilder::new()
+            .section(Section::new(".text", 0x1000, vec![0xC3; 0x10]))
+            .size_of_image(MAX_IMAGE_SIZE + 0x1000)
+            .library();
+        let pe = library.pe_file().unwrap();
+
+        assert!(matches!(
+            map_image(&pe, &library),
+            Err(Error::ImageTooLarge(size)) if size == MAX_IMAGE_SIZE + 0x1000
+        ));
+    }
+}
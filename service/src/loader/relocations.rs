@@ -1,39 +1,151 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use net_syringe_common::fixups::{
+    IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_HIGHADJ, Relocation, SUPPORTED_RELOCATIONS,
+    apply_relocation,
+};
 use pelite::{
-    image::IMAGE_REL_BASED_DIR64,
-    pe::{Pe, PeFile},
+    image::IMAGE_FILE_RELOCS_STRIPPED,
+    pe64::{Pe, PeFile},
 };
 
-pub fn apply_relocations(
-    pe: &PeFile,
-    mapped_image: &mut [u8],
-    allocation_base: u64,
-) -> Result<(), ()> {
-    let delta_base = allocation_base.wrapping_sub(pe.optional_header().ImageBase);
+/// Returns the image's base relocations, or `None` if relocation information was stripped.
+pub fn get_relocations(pe: &PeFile) -> Result<Option<Vec<Relocation>>> {
+    if pe.file_header().Characteristics & IMAGE_FILE_RELOCS_STRIPPED != 0 {
+        return Ok(None);
+    }
 
     let relocs = match pe.base_relocs() {
         Ok(relocs) => relocs,
-        Err(_) => return Ok(()),
+        Err(pelite::Error::Null) => return Ok(None),
+        Err(err) => return Err(err.into()),
     };
 
+    let mut relocations = Vec::new();
+
     for block in relocs.iter_blocks() {
-        for word in block.words() {
-            let ty = block.type_of(word);
-            if ty == IMAGE_REL_BASED_DIR64 {
-                let rva = block.rva_of(word) as usize;
-
-                let slot: &mut [u8; 8] = mapped_image
-                    .get_mut(rva..rva + 8)
-                    .ok_or(())?
-                    .try_into()
-                    .map_err(|_| ())?;
-
-                *slot = u64::from_ne_bytes(*slot)
-                    .wrapping_add(delta_base)
-                    .to_ne_bytes();
+        // pelite never advances past a block whose size wraps around when aligned.
+        if block.image().SizeOfBlock as usize > 8 + block.words().len() * 2 {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut words = block.words().iter();
+
+        while let Some(word) = words.next() {
+            let kind = block.type_of(word);
+            let rva = block.rva_of(word);
+
+            // HIGHADJ stores the low half of the target in the following entry.
+            let param = if kind == IMAGE_REL_BASED_HIGHADJ {
+                *words.next().ok_or(Error::OutOfRange)?
+            } else {
+                0
+            };
+
+            if kind == IMAGE_REL_BASED_ABSOLUTE {
+                continue;
+            }
+
+            if !SUPPORTED_RELOCATIONS.contains(&kind) {
+                return Err(net_syringe_common::Error::UnsupportedRelocation { kind, rva }.into());
             }
+
+            relocations.push(Relocation { rva, kind, param });
+        }
+    }
+
+    Ok(Some(relocations))
+}
+
+pub fn apply_relocations(pe: &PeFile, mapped_image: &mut [u8], allocation_base: u64) -> Result<()> {
+    let image_base = pe.optional_header().ImageBase;
+    let delta_base = allocation_base.wrapping_sub(image_base);
+
+    match get_relocations(pe)? {
+        Some(relocations) => {
+            for reloc in &relocations {
+                apply_relocation(mapped_image, reloc, delta_base)?;
+            }
+        }
+        None if delta_base != 0 => {
+            return Err(net_syringe_common::Error::RelocationsStripped(image_base).into());
         }
+        None => {}
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{IMAGE_BASE, IMAGE_DIRECTORY_ENTRY_BASERELOC, PeBuilder, Section};
+    use net_syringe_common::fixups::IMAGE_REL_BASED_DIR64;
+
+    fn block(virtual_address: u32, size_of_block: u32, words: &[u16]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&virtual_address.to_le_bytes());
+        block.extend_from_slice(&size_of_block.to_le_bytes());
+        for word in words {
+            block.extend_from_slice(&word.to_le_bytes());
+        }
+        block
+    }
+
+    fn library(relocs: Vec<u8>) -> crate::library::Library {
+        let size = relocs.len() as u32;
+
+        PeBuilder::new()
+            .section(Section::new(".data", 0x1000, vec![0; 0x20]))
+            .section(Section::new(".reloc", 0x2000, relocs))
+            .directory(IMAGE_DIRECTORY_ENTRY_BASERELOC, 0x2000, size)
+            .library()
+    }
+
+    #[test]
+    fn reads_relocations() {
+        let library = library(block(0x1000, 12, &[0xA008, 0x0000]));
+        let pe = library.pe_file().unwrap();
+
+        assert_eq!(
+            get_relocations(&pe).unwrap().unwrap(),
+            [Relocation {
+                rva: 0x1008,
+                kind: IMAGE_REL_BASED_DIR64,
+                param: 0,
+            }]
+        );
+
+        let mut image = vec![0u8; 0x3000];
+        image[0x1008..0x1010].copy_from_slice(&(IMAGE_BASE + 0x1000).to_le_bytes());
+        apply_relocations(&pe, &mut image, 0x2_0000_0000).unwrap();
+
+        assert_eq!(image[0x1008..0x1010], 0x2_0000_1000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn refuses_blocks_larger_than_the_directory() {
+        // Aligned to 4 bytes, this size wraps to 0 and pelite would never advance past it.
+        let mut relocs = block(0x1000, 12, &[0xA008, 0x0000]);
+        relocs.extend(block(0x1000, 0xFFFF_FFFD, &[0xA010, 0x0000]));
+        let library = library(relocs);
+        let pe = library.pe_file().unwrap();
+
+        assert!(matches!(get_relocations(&pe), Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn refuses_unsupported_relocations() {
+        let library = library(block(0x1000, 12, &[0x9008, 0x0000]));
+        let pe = library.pe_file().unwrap();
+
+        assert!(matches!(
+            get_relocations(&pe),
+            Err(Error::Fixup(
+                net_syringe_common::Error::UnsupportedRelocation {
+                    kind: 9,
+                    rva: 0x1008
+                }
+            ))
+        ));
+    }
+}
@@ -1,29 +1,184 @@
-use pelite::{
-    image::{IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_LOAD_CONFIG_DIRECTORY64},
-    pe::{Pe, PeFile},
-};
+//! `__security_cookie` initialization as done by the Windows loader before the CRT runs.
+
+use crate::error::{Error, Result};
+use pelite::pe64;
+
+/// Value the MSVC CRT links `__security_cookie` with on x64.
+pub const DEFAULT_SECURITY_COOKIE_64: u64 = 0x0000_2B99_2DDF_A232;
+
+const COOKIE_SIZE: usize = size_of::<u64>();
+
+/// Turns a random value into a cookie that passes the checks `__security_init_cookie` performs,
+/// which require the upper 16 bits of x64 cookies to be clear.
+pub fn make_security_cookie(random: u64) -> u64 {
+    let mut cookie = random & 0x0000_FFFF_FFFF_FFFF;
+
+    if cookie == 0 || cookie == DEFAULT_SECURITY_COOKIE_64 {
+        cookie = DEFAULT_SECURITY_COOKIE_64 + 1;
+    }
+
+    cookie
+}
+
+/// Writes a cookie generated from `random` to the `__security_cookie` at `rva`, and its complement
+/// to `__security_cookie_complement` if it directly follows the cookie. Cookies that are not the
+/// linker default are left alone, like the loader does.
+pub fn write_security_cookie(mapped_image: &mut [u8], rva: usize, random: u64) -> Result<()> {
+    if read_cookie(mapped_image, rva).ok_or(Error::OutOfRange)? != DEFAULT_SECURITY_COOKIE_64 {
+        return Ok(());
+    }
+
+    let cookie = make_security_cookie(random);
+    write_cookie(mapped_image, rva, cookie);
+
+    let complement_rva = rva + COOKIE_SIZE;
+    if read_cookie(mapped_image, complement_rva) == Some(!DEFAULT_SECURITY_COOKIE_64) {
+        write_cookie(mapped_image, complement_rva, !cookie);
+    }
+
+    Ok(())
+}
+
+fn read_cookie(mapped_image: &[u8], rva: usize) -> Option<u64> {
+    let bytes = mapped_image.get(rva..rva.checked_add(COOKIE_SIZE)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn write_cookie(mapped_image: &mut [u8], rva: usize, cookie: u64) {
+    mapped_image[rva..rva + COOKIE_SIZE].copy_from_slice(&cookie.to_le_bytes());
+}
+
+pub fn init_security_cookie(pe: &pe64::PeFile, mapped_image: &mut [u8]) -> Result<()> {
+    use pelite::pe64::Pe;
 
-pub fn init_security_cookie(pe: &PeFile, mapped_image: &mut [u8]) -> pelite::Result<()> {
     let load_config = match pe.load_config() {
         Err(pelite::Error::Null) => return Ok(()),
         value => value?,
     };
 
-    let mapped_load_config_dir = pe.data_directory()[IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG];
-    if mapped_load_config_dir.VirtualAddress == 0 || mapped_load_config_dir.Size == 0 {
+    let cookie_va = load_config.image().SecurityCookie;
+    if cookie_va == 0 {
         return Ok(());
     }
 
-    let mapped_load_config = mapped_image
-        .get_mut(
-            mapped_load_config_dir.VirtualAddress as usize
-                ..mapped_load_config_dir.VirtualAddress as usize
-                    + std::mem::size_of::<IMAGE_LOAD_CONFIG_DIRECTORY64>(),
-        )
-        .ok_or(pelite::Error::Overflow)? as *mut _
-        as *mut IMAGE_LOAD_CONFIG_DIRECTORY64;
+    let rva = pe.va_to_rva(cookie_va)? as usize;
+    write_security_cookie(mapped_image, rva, rand::random())
+}
 
-    (unsafe { *mapped_load_config }).SecurityCookie = *load_config.security_cookie()? as u64 + 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loader::map_image, testing::fixture};
 
-    Ok(())
+    #[test]
+    fn replaces_the_default_cookie() {
+        assert_eq!(
+            make_security_cookie(DEFAULT_SECURITY_COOKIE_64),
+            DEFAULT_SECURITY_COOKIE_64 + 1
+        );
+        // Bits outside the cookie do not make it differ from the default.
+        assert_eq!(
+            make_security_cookie(0xFFFF_0000_0000_0000 | DEFAULT_SECURITY_COOKIE_64),
+            DEFAULT_SECURITY_COOKIE_64 + 1
+        );
+    }
+
+    #[test]
+    fn replaces_zero() {
+        assert_eq!(make_security_cookie(0), DEFAULT_SECURITY_COOKIE_64 + 1);
+        assert_eq!(
+            make_security_cookie(0xFFFF_0000_0000_0000),
+            DEFAULT_SECURITY_COOKIE_64 + 1
+        );
+    }
+
+    #[test]
+    fn clears_the_upper_16_bits() {
+        assert_eq!(make_security_cookie(u64::MAX), 0x0000_FFFF_FFFF_FFFF);
+        assert_eq!(
+            make_security_cookie(0x1234_5678_9ABC_DEF0),
+            0x0000_5678_9ABC_DEF0
+        );
+    }
+
+    fn image(cookie: u64, complement: u64) -> Vec<u8> {
+        let mut image = vec![0u8; 0x20];
+        image[0x10..0x18].copy_from_slice(&cookie.to_le_bytes());
+        image[0x18..0x20].copy_from_slice(&complement.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn updates_the_complement() {
+        let mut image = image(DEFAULT_SECURITY_COOKIE_64, !DEFAULT_SECURITY_COOKIE_64);
+
+        write_security_cookie(&mut image, 0x10, 0x1234_5678_9ABC).unwrap();
+
+        let cookie = read_cookie(&image, 0x10).unwrap();
+        assert_eq!(cookie, make_security_cookie(0x1234_5678_9ABC));
+        assert_eq!(read_cookie(&image, 0x18), Some(!cookie));
+    }
+
+    #[test]
+    fn skips_a_complement_that_does_not_match() {
+        let mut image = image(DEFAULT_SECURITY_COOKIE_64, 0x1111);
+
+        write_security_cookie(&mut image, 0x10, 0x1234).unwrap();
+
+        assert_eq!(read_cookie(&image, 0x10), Some(0x1234));
+        assert_eq!(read_cookie(&image, 0x18), Some(0x1111));
+
+        // A cookie at the end of the image has no complement to read.
+        let mut image = DEFAULT_SECURITY_COOKIE_64.to_le_bytes().to_vec();
+        write_security_cookie(&mut image, 0, 0x1234).unwrap();
+        assert_eq!(image, 0x1234u64.to_le_bytes());
+    }
+
+    #[test]
+    fn skips_cookies_that_are_not_the_default() {
+        let mut image = image(0x1234, !0x1234);
+        let original = image.clone();
+
+        write_security_cookie(&mut image, 0x10, 0x5678).unwrap();
+
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn refuses_cookies_outside_the_image() {
+        let mut image = vec![0u8; 0x10];
+
+        assert!(matches!(
+            write_security_cookie(&mut image, 0xC, 0),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            write_security_cookie(&mut image, usize::MAX, 0),
+            Err(Error::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn initializes_the_cookie_of_an_msvc_image() {
+        use pelite::pe64::Pe;
+
+        let library = fixture("msvc.dll");
+        let pe = library.pe_file().unwrap();
+        let mut mapped_image = map_image(&pe, &library).unwrap();
+
+        let rva = pe
+            .va_to_rva(pe.load_config().unwrap().image().SecurityCookie)
+            .unwrap() as usize;
+        assert_eq!(
+            read_cookie(&mapped_image, rva),
+            Some(DEFAULT_SECURITY_COOKIE_64)
+        );
+
+        init_security_cookie(&pe, &mut mapped_image).unwrap();
+
+        let cookie = read_cookie(&mapped_image, rva).unwrap();
+        assert_ne!(cookie, DEFAULT_SECURITY_COOKIE_64);
+        assert_ne!(cookie, 0);
+        assert_eq!(cookie >> 48, 0);
+    }
 }
@@ -1,17 +1,47 @@
-use axum::{Router, http::StatusCode, routing::get};
+use clap::Parser;
+use service::openapi;
+use std::path::PathBuf;
 use tokio::net::TcpListener;
-use tower_http::trace::TraceLayer;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod dto;
-mod error;
-mod library;
-mod loader;
-mod routes;
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long, help = "Print the OpenAPI document and exit")]
+    print_openapi: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Fail if FILE differs from the generated OpenAPI document"
+    )]
+    check_openapi: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if args.print_openapi {
+        println!("{}", openapi::spec_json());
+        return Ok(());
+    }
+
+    if let Some(path) = args.check_openapi {
+        let committed: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)?;
+        let generated: serde_json::Value = serde_json::from_str(&openapi::spec_json())?;
+
+        if committed != generated {
+            return Err(format!(
+                "{} is out of date, regenerate it with --print-openapi",
+                path.display()
+            )
+            .into());
+        }
+        return Ok(());
+    }
+
     dotenv::dotenv()?;
 
     let port = dotenv::var("PORT").unwrap_or("3000".to_string());
@@ -29,17 +59,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let app = Router::new()
-        .route("/", get(|| async { StatusCode::OK }))
-        .route(
-            "/libraries/{library_id}",
-            get(routes::libraries::get_library),
-        )
-        .route(
-            "/libraries/{library_id}/mapping",
-            get(routes::libraries::get_library_mapping),
-        )
-        .layer(TraceLayer::new_for_http());
+    let app = service::router();
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
 
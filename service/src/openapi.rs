@@ -0,0 +1,35 @@
+use crate::routes;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    routes::version::get_version,
+    routes::libraries::get_library,
+    routes::libraries::get_library_export,
+    routes::libraries::get_library_mapping,
+    routes::libraries::get_library_image,
+))]
+pub struct ApiDoc;
+
+pub fn spec_json() -> String {
+    ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("openapi document serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn committed_spec_is_up_to_date() {
+        let committed: serde_json::Value =
+            serde_json::from_str(include_str!("../openapi.json")).unwrap();
+        let generated: serde_json::Value = serde_json::from_str(&spec_json()).unwrap();
+
+        assert!(
+            committed == generated,
+            "service/openapi.json is out of date, regenerate it with --print-openapi"
+        );
+    }
+}
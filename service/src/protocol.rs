@@ -0,0 +1,201 @@
+use crate::{
+    dto::{self, Feature},
+    routes::ApiError,
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, request::Parts},
+};
+use utoipa::IntoParams;
+
+/// Protocol version and features stated by the client in the request headers.
+#[derive(Debug, IntoParams)]
+#[into_params(parameter_in = Header)]
+pub struct ClientProtocol {
+    /// Protocol version of the client, version 1 is assumed if missing.
+    #[param(rename = "x-net-syringe-protocol", value_type = Option<u32>)]
+    pub version: u32,
+    /// Comma-separated features supported by the client.
+    #[param(rename = "x-net-syringe-features", value_type = Option<String>)]
+    pub features: Vec<Feature>,
+}
+
+impl ClientProtocol {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// Refuses the request if the service or the client lacks any of the `required` features.
+    pub fn require(&self, required: &[Feature]) -> Result<(), ApiError> {
+        let unsupported = missing_features(required, |feature| {
+            dto::SUPPORTED_FEATURES.contains(&feature)
+        });
+        if !unsupported.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("library requires features not supported by this service: {unsupported}"),
+            ));
+        }
+
+        let unsupported = missing_features(required, |feature| self.supports(feature));
+        if !unsupported.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                format!("library requires features not supported by this client: {unsupported}"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Refuses the request if the client predates protocol `version`, which `what` needs.
+    pub fn require_version(&self, version: u32, what: &str) -> Result<(), ApiError> {
+        if self.version < version {
+            return Err(ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                format!(
+                    "{what} requires protocol version {version}, the client uses {}",
+                    self.version
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn missing_features(required: &[Feature], supports: impl Fn(Feature) -> bool) -> String {
+    required
+        .iter()
+        .filter(|feature| !supports(**feature))
+        .map(|feature| feature.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientProtocol {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Clients predating the handshake send no headers; their response shape is version 1.
+        let version = match parts.headers.get(dto::PROTOCOL_VERSION_HEADER) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid {} header", dto::PROTOCOL_VERSION_HEADER),
+                ))?,
+            None => dto::MIN_PROTOCOL_VERSION,
+        };
+
+        if !(dto::MIN_PROTOCOL_VERSION..=dto::PROTOCOL_VERSION).contains(&version) {
+            return Err(ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                format!(
+                    "unsupported protocol version {}, service supports versions {} to {}",
+                    version,
+                    dto::MIN_PROTOCOL_VERSION,
+                    dto::PROTOCOL_VERSION
+                ),
+            ));
+        }
+
+        // Unknown features are ignored so newer clients can talk to older services.
+        let features = parts
+            .headers
+            .get_all(dto::PROTOCOL_FEATURES_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|value| value.trim().parse().ok())
+            .collect();
+
+        Ok(Self { version, features })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn extract(version: Option<&str>) -> Result<ClientProtocol, ApiError> {
+        let mut request = Request::builder();
+        if let Some(version) = version {
+            request = request.header(dto::PROTOCOL_VERSION_HEADER, version);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+
+        ClientProtocol::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn reads_the_version() {
+        assert_eq!(extract(None).await.unwrap().version, 1);
+        assert_eq!(extract(Some("2")).await.unwrap().version, 2);
+
+        assert_eq!(
+            extract(Some("x")).await.unwrap_err().status,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            extract(Some("3")).await.unwrap_err().status,
+            StatusCode::PRECONDITION_FAILED
+        );
+    }
+
+    #[test]
+    fn refuses_clients_predating_a_version() {
+        let protocol = |version| ClientProtocol {
+            version,
+            features: Vec::new(),
+        };
+
+        let err = protocol(1)
+            .require_version(
+                dto::OPTIONAL_ENTRY_POINT_VERSION,
+                "library without an entry point",
+            )
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(
+            err.message.as_deref(),
+            Some("library without an entry point requires protocol version 2, the client uses 1")
+        );
+
+        assert!(
+            protocol(2)
+                .require_version(
+                    dto::OPTIONAL_ENTRY_POINT_VERSION,
+                    "library without an entry point"
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn refuses_features_the_service_or_client_lacks() {
+        let protocol = ClientProtocol {
+            version: dto::PROTOCOL_VERSION,
+            features: vec![Feature::TlsCallbacks],
+        };
+
+        let err = protocol.require(&[Feature::TlsCallbacks]).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            err.message.as_deref(),
+            Some("library requires features not supported by this service: tls-callbacks")
+        );
+
+        let err = protocol.require(&[Feature::ExceptionTables]).unwrap_err();
+        assert_eq!(err.status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(
+            err.message.as_deref(),
+            Some("library requires features not supported by this client: exception-tables")
+        );
+
+        assert!(protocol.require(&[]).is_ok());
+    }
+}
@@ -1,59 +1,210 @@
 use crate::{
     dto::{self, GetLibraryMapping},
     error::Result,
-    library::Library,
+    library::{Library, LibraryDir},
     loader::{
-        apply_relocations, get_library_imports, init_security_cookie, map_image, resolve_imports,
+        apply_relocations, get_discardable_sections, get_entry_point, get_exception_table,
+        get_export, get_fixup_table, get_library_imports, get_regions, get_relocations,
+        get_required_features, get_tls_callbacks, init_security_cookie, map_image, patch_guard_cf,
+        resolve_imports,
     },
+    protocol::ClientProtocol,
+    routes::ApiError,
 };
-use axum::{Json, extract::Path, http::StatusCode};
-use pelite::{
-    image::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE},
-    pe::{Pe, PeFile},
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use net_syringe_common::{envelope, fixups::FixupTable};
+use pelite::pe64::{Pe, PeFile};
 use tokio::io;
 use tracing::{debug, warn};
 
-pub async fn get_library(
-    library_id: Path<String>,
-) -> Result<Json<dto::GetLibraryResponse>, StatusCode> {
-    let library = Library::get(&library_id)
-        .await
-        .map_err(|err| match err.kind() {
+async fn load_library(libraries: &LibraryDir, library_id: &str) -> Result<Library, ApiError> {
+    Library::get(libraries, library_id).await.map_err(|err| {
+        match err.kind() {
             io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
             io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+        }
+        .into()
+    })
+}
+
+/// Parses the library and refuses it if the client or service lacks the features it requires.
+fn parse_library<'a>(
+    library: &'a Library,
+    protocol: &ClientProtocol,
+) -> Result<PeFile<'a>, ApiError> {
+    if library.is_pe32() {
+        protocol.require(&[dto::Feature::Pe32])?;
+    }
+
+    let pe = library
+        .pe_file()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    protocol
+        .require(&get_required_features(&pe).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)?;
+
+    // Version 1 clients call the entry point unconditionally, at offset 0 if there is none.
+    if get_entry_point(&pe).is_none() {
+        protocol.require_version(
+            dto::OPTIONAL_ENTRY_POINT_VERSION,
+            "library without an entry point",
+        )?;
+    }
+
+    Ok(pe)
+}
+
+fn envelope_response(
+    pe: &PeFile,
+    mapped_image: Vec<u8>,
+    fixups: Option<FixupTable>,
+) -> Result<Response, ApiError> {
+    let envelope = envelope::Envelope {
+        entry_point: get_entry_point(pe),
+        regions: get_regions(pe)
+            .into_iter()
+            .map(|region| envelope::Region {
+                rva: region.rva,
+                size: region.size,
+                characteristics: region.characteristics,
+            })
+            .collect(),
+        tls_callbacks: get_tls_callbacks(pe).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        exception_table: get_exception_table(pe),
+        fixups,
+        discardable: get_discardable_sections(pe),
+        image: mapped_image,
+    };
+
+    let body = envelope::encode(&envelope);
 
-    let pe =
-        PeFile::from_bytes(&library.file_data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    debug!(
+        image_size = envelope.image.len(),
+        envelope_size = body.len(),
+        "encoded envelope"
+    );
+
+    Ok(([(header::CONTENT_TYPE, envelope::CONTENT_TYPE)], body).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/libraries/{library_id}",
+    params(
+        ("library_id" = String, Path, description = "ID of the library"),
+        ClientProtocol,
+    ),
+    responses(
+        (status = 200, description = "Library metadata", body = dto::GetLibraryResponse),
+        (status = 400, description = "Invalid library ID or protocol headers"),
+        (status = 404, description = "Library not found"),
+        (status = 412, description = "Unsupported protocol version or client features", body = String),
+        (status = 422, description = "Library requires features this service lacks", body = String),
+    )
+)]
+pub async fn get_library(
+    State(libraries): State<LibraryDir>,
+    library_id: Path<String>,
+    protocol: ClientProtocol,
+) -> Result<Json<dto::GetLibraryResponse>, ApiError> {
+    let library = load_library(&libraries, &library_id).await?;
+    let pe = parse_library(&library, &protocol)?;
 
     let imports = get_library_imports(&pe).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let regions = pe
-        .section_headers()
-        .iter()
-        .map(|sect| dto::Region {
-            rva: sect.VirtualAddress,
-            size: sect.VirtualSize,
-            characteristics: sect.Characteristics
-                & (IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE | IMAGE_SCN_MEM_EXECUTE),
-        })
-        .collect();
+    let relocs_stripped = get_relocations(&pe)?.is_none();
 
     Ok(Json(dto::GetLibraryResponse {
         size_of_image: pe.optional_header().SizeOfImage,
-        entry_point: pe.optional_header().AddressOfEntryPoint,
+        entry_point: get_entry_point(&pe),
+        image_base: pe.optional_header().ImageBase,
+        relocs_stripped,
         imports,
-        regions,
+        regions: get_regions(&pe),
+        discardable: get_discardable_sections(&pe)
+            .into_iter()
+            .map(|section| dto::DiscardableSection {
+                name: section.name,
+                rva: section.rva,
+                size: section.size,
+            })
+            .collect(),
+        sha256: library.sha256(),
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/libraries/{library_id}/export",
+    params(
+        ("library_id" = String, Path, description = "ID of the library"),
+        ClientProtocol,
+        dto::GetLibraryExport,
+    ),
+    responses(
+        (status = 200, description = "RVA of the exported function", body = dto::GetLibraryExportResponse),
+        (status = 400, description = "Invalid library ID or protocol headers"),
+        (status = 404, description = "Library or export not found"),
+        (status = 412, description = "Unsupported protocol version or client features", body = String),
+        (status = 422, description = "Library requires features this service lacks", body = String),
+    )
+)]
+pub async fn get_library_export(
+    State(libraries): State<LibraryDir>,
+    library_id: Path<String>,
+    protocol: ClientProtocol,
+    Query(query): Query<dto::GetLibraryExport>,
+) -> Result<Json<dto::GetLibraryExportResponse>, ApiError> {
+    let library = load_library(&libraries, &library_id).await?;
+    let pe = parse_library(&library, &protocol)?;
+
+    let rva = get_export(&pe, &query.name)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(dto::GetLibraryExportResponse {
+        name: query.name,
+        rva,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/libraries/{library_id}/mapping",
+    params(
+        ("library_id" = String, Path, description = "ID of the library"),
+        ClientProtocol,
+    ),
+    request_body = dto::GetLibraryMapping,
+    responses(
+        (status = 200, description = "Mapped image, as raw bytes or as a binary envelope with the image metadata", content(
+            (Vec<u8> = "application/octet-stream"),
+            (Vec<u8> = "application/vnd.net-syringe.image"),
+        )),
+        (status = 400, description = "Invalid library ID, protocol headers or missing import addresses"),
+        (status = 404, description = "Library not found"),
+        (status = 412, description = "Unsupported protocol version or client features", body = String),
+        (status = 422, description = "Library requires features this service lacks or guard functions that were not supplied", body = String),
+    )
+)]
 pub async fn get_library_mapping(
+    State(libraries): State<LibraryDir>,
     library_id: Path<String>,
+    protocol: ClientProtocol,
+    headers: HeaderMap,
     body: Json<GetLibraryMapping>,
-) -> Result<Vec<u8>, StatusCode> {
-    debug!(library = library_id.0, "starting mapping");
+) -> Result<Response, ApiError> {
+    debug!(
+        library = library_id.0,
+        protocol = protocol.version,
+        "starting mapping"
+    );
 
     if !body.imports.iter().all(|(_, imports)| {
         imports.iter().all(|import| match import {
@@ -65,20 +216,12 @@ pub async fn get_library_mapping(
             library = library_id.0,
             "not all import addresses were provided"
         );
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(StatusCode::BAD_REQUEST.into());
     }
 
-    let library = Library::get(&library_id)
-        .await
-        .map_err(|err| match err.kind() {
-            io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
-            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    let library = load_library(&libraries, &library_id).await?;
+    let pe = parse_library(&library, &protocol)?;
 
-    let pe =
-        PeFile::from_bytes(&library.file_data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        
     debug!(library = library_id.0, "mapping image");
 
     let mut mapped_image =
@@ -98,10 +241,77 @@ pub async fn get_library_mapping(
 
     debug!(library = library_id.0, "relocating image");
 
-    apply_relocations(&pe, &mut mapped_image, body.allocation_base)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    apply_relocations(&pe, &mut mapped_image, body.allocation_base)?;
+
+    // The guard function pointers hold absolute addresses and must not be relocated.
+    patch_guard_cf(&pe, &mut mapped_image, body.guard_cf.as_ref())?;
+
+    debug!(library = library_id.0, "finished mapping");
+
+    let wants_envelope = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(envelope::CONTENT_TYPE));
+
+    if !wants_envelope {
+        return Ok(mapped_image.into_response());
+    }
+
+    envelope_response(&pe, mapped_image, None)
+}
+
+#[utoipa::path(
+    get,
+    path = "/libraries/{library_id}/image",
+    params(
+        ("library_id" = String, Path, description = "ID of the library"),
+        ClientProtocol,
+        dto::GetLibraryImage,
+    ),
+    responses(
+        (status = 200, description = "Base-independent image with the relocation and import fixups for the client to apply", body = Vec<u8>, content_type = "application/vnd.net-syringe.image"),
+        (status = 400, description = "Invalid library ID or protocol headers"),
+        (status = 404, description = "Library not found"),
+        (status = 412, description = "Unsupported protocol version or client features", body = String),
+        (status = 422, description = "Library requires features this service lacks or guard functions that were not supplied", body = String),
+    )
+)]
+pub async fn get_library_image(
+    State(libraries): State<LibraryDir>,
+    library_id: Path<String>,
+    protocol: ClientProtocol,
+    query: Query<dto::GetLibraryImage>,
+) -> Result<Response, ApiError> {
+    debug!(
+        library = library_id.0,
+        protocol = protocol.version,
+        "starting base-independent mapping"
+    );
+
+    let library = load_library(&libraries, &library_id).await?;
+    let pe = parse_library(&library, &protocol)?;
+
+    let mut mapped_image =
+        map_image(&pe, &library).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if library.init_security_cookie {
+        debug!(library = library_id.0, "initializing security cookie");
+
+        init_security_cookie(&pe, &mut mapped_image)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    debug!(library = library_id.0, "collecting fixups");
+
+    let mut fixups = get_fixup_table(&pe)?;
+
+    let guard_cf_slots = patch_guard_cf(&pe, &mut mapped_image, query.guard_cf().as_ref())?;
+    fixups
+        .relocations
+        .retain(|reloc| !guard_cf_slots.contains(&reloc.rva));
 
     debug!(library = library_id.0, "finished mapping");
 
-    Ok(mapped_image)
+    envelope_response(&pe, mapped_image, Some(fixups))
 }
@@ -1 +1,57 @@
+use crate::error::Error;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
 pub mod libraries;
+pub mod version;
+
+/// Error response with an optional message for the client, returned as the response body.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: Option<String>,
+}
+
+impl ApiError {
+    pub fn new<S: Into<String>>(status: StatusCode, message: S) -> Self {
+        Self {
+            status,
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        Self {
+            status,
+            message: None,
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Fixup(
+                net_syringe_common::Error::UnsupportedRelocation { .. }
+                | net_syringe_common::Error::RelocationsStripped(_),
+            )
+            | Error::ControlFlowGuard => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self.message {
+            Some(message) => (self.status, message).into_response(),
+            None => self.status.into_response(),
+        }
+    }
+}
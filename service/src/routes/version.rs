@@ -0,0 +1,17 @@
+use crate::dto;
+use axum::Json;
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = 200, description = "Supported protocol versions and features", body = dto::GetVersionResponse),
+    )
+)]
+pub async fn get_version() -> Json<dto::GetVersionResponse> {
+    Json(dto::GetVersionResponse {
+        protocol_version: dto::PROTOCOL_VERSION,
+        min_protocol_version: dto::MIN_PROTOCOL_VERSION,
+        features: dto::SUPPORTED_FEATURES.to_vec(),
+    })
+}
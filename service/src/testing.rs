@@ -0,0 +1,306 @@
+//! Synthetic PE32+ images for tests that need header values no linker produces.
+
+use crate::library::Library;
+use std::path::Path;
+
+pub const IMAGE_BASE: u64 = 0x1_8000_0000;
+
+const SIZE_OF_HEADERS: u32 = 0x400;
+const NT_HEADERS: usize = 0x40;
+const OPTIONAL_HEADER: usize = NT_HEADERS + 24;
+const SIZE_OF_OPTIONAL_HEADER: usize = 0xF0;
+const SECTION_HEADERS: usize = OPTIONAL_HEADER + SIZE_OF_OPTIONAL_HEADER;
+
+pub const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+pub const IMAGE_DIRECTORY_ENTRY_BASERELOC: usize = 5;
+
+fn align_up(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment) * alignment
+}
+
+fn write(file: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+    if file.len() < offset + bytes.len() {
+        file.resize(offset + bytes.len(), 0);
+    }
+    file[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+pub struct Section {
+    pub name: &'static str,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    /// `None` places the data after the previous section's, on the file alignment.
+    pub pointer_to_raw_data: Option<u32>,
+    /// `None` is the length of `data` rounded up to the file alignment.
+    pub size_of_raw_data: Option<u32>,
+    pub characteristics: u32,
+    pub data: Vec<u8>,
+}
+
+impl Section {
+    /// An initialized, readable section as large as its data.
+    pub fn new(name: &'static str, virtual_address: u32, data: Vec<u8>) -> Self {
+        Self {
+            name,
+            virtual_address,
+            virtual_size: data.len() as u32,
+            pointer_to_raw_data: None,
+            size_of_raw_data: None,
+            characteristics: 0x4000_0040,
+            data,
+        }
+    }
+}
+
+pub struct PeBuilder {
+    file_alignment: u32,
+    section_alignment: u32,
+    size_of_image: Option<u32>,
+    file_size: Option<usize>,
+    sections: Vec<Section>,
+    imports: Vec<(&'static str, Vec<&'static str>)>,
+    directories: Vec<(usize, u32, u32)>,
+}
+
+impl PeBuilder {
+    pub fn new() -> Self {
+        Self {
+            file_alignment: 0x200,
+            section_alignment: 0x1000,
+            size_of_image: None,
+            file_size: None,
+            sections: Vec::new(),
+            imports: Vec::new(),
+            directories: Vec::new(),
+        }
+    }
+
+    pub fn alignment(mut self, file_alignment: u32, section_alignment: u32) -> Self {
+        self.file_alignment = file_alignment;
+        self.section_alignment = section_alignment;
+        self
+    }
+
+    /// Cuts off or pads the file, which otherwise ends with the last section's raw data.
+    pub fn file_size(mut self, size: usize) -> Self {
+        self.file_size = Some(size);
+        self
+    }
+
+    /// Overrides `SizeOfImage`, which otherwise covers the last section.
+    pub fn size_of_image(mut self, size: u32) -> Self {
+        self.size_of_image = Some(size);
+        self
+    }
+
+    pub fn section(mut self, section: Section) -> Self {
+        self.sections.push(section);
+        self
+    }
+
+    /// Adds an import descriptor for `dll` importing `names`, placed in an `.idata` section after
+    /// the others. Each call adds a descriptor, even for a DLL that is already imported.
+    pub fn import(mut self, dll: &'static str, names: &[&'static str]) -> Self {
+        self.imports.push((dll, names.to_vec()));
+        self
+    }
+
+    pub fn directory(mut self, index: usize, rva: u32, size: u32) -> Self {
+        self.directories.push((index, rva, size));
+        self
+    }
+
+    /// Lays out the descriptors, then per descriptor the lookup and address tables, then the
+    /// DLL names and hint/name entries.
+    fn import_section(&self, virtual_address: u32) -> Section {
+        let mut data = vec![0u8; (self.imports.len() + 1) * 20];
+        let mut strings = Vec::new();
+
+        for (idx, (dll, names)) in self.imports.iter().enumerate() {
+            let table_size = (names.len() + 1) * 8;
+            let int_rva = data.len().next_multiple_of(8);
+            let iat_rva = int_rva + table_size;
+            data.resize(iat_rva + table_size, 0);
+
+            let descriptor = idx * 20;
+            let rva = |offset: usize| (virtual_address + offset as u32).to_le_bytes();
+            data[descriptor..descriptor + 4].copy_from_slice(&rva(int_rva));
+            data[descriptor + 16..descriptor + 20].copy_from_slice(&rva(iat_rva));
+
+            strings.push((descriptor + 12, None, dll.as_bytes()));
+            for (thunk, name) in names.iter().enumerate() {
+                strings.push((
+                    int_rva + thunk * 8,
+                    Some(iat_rva + thunk * 8),
+                    name.as_bytes(),
+                ));
+            }
+        }
+
+        for (slot, iat_slot, name) in strings {
+            let offset = data.len();
+            let rva = virtual_address + offset as u32;
+
+            match iat_slot {
+                // The DLL name is referenced by a 32-bit RVA.
+                None => {
+                    data[slot..slot + 4].copy_from_slice(&rva.to_le_bytes());
+                    data.extend_from_slice(name);
+                }
+                // Hint/name entries are referenced by 64-bit thunks in both tables.
+                Some(iat_slot) => {
+                    data[slot..slot + 8].copy_from_slice(&(rva as u64).to_le_bytes());
+                    data[iat_slot..iat_slot + 8].copy_from_slice(&(rva as u64).to_le_bytes());
+                    data.extend_from_slice(&0u16.to_le_bytes());
+                    data.extend_from_slice(name);
+                }
+            }
+
+            data.push(0);
+            data.resize(data.len().next_multiple_of(2), 0);
+        }
+
+        Section {
+            characteristics: 0xC000_0040,
+            ..Section::new(".idata", virtual_address, data)
+        }
+    }
+
+    pub fn build(mut self) -> Vec<u8> {
+        let section_end = |section: &Section| {
+            section.virtual_address + section.virtual_size.max(section.data.len() as u32)
+        };
+
+        if !self.imports.is_empty() {
+            let virtual_address = align_up(
+                self.sections
+                    .iter()
+                    .map(section_end)
+                    .max()
+                    .unwrap_or(SIZE_OF_HEADERS),
+                self.section_alignment,
+            );
+            let section = self.import_section(virtual_address);
+
+            self.directories.push((
+                IMAGE_DIRECTORY_ENTRY_IMPORT,
+                virtual_address,
+                (self.imports.len() as u32 + 1) * 20,
+            ));
+            self.sections.push(section);
+        }
+
+        let size_of_image = self.size_of_image.unwrap_or_else(|| {
+            align_up(
+                self.sections
+                    .iter()
+                    .map(section_end)
+                    .max()
+                    .unwrap_or(SIZE_OF_HEADERS),
+                self.section_alignment,
+            )
+        });
+
+        let mut file = vec![0u8; SIZE_OF_HEADERS as usize];
+
+        // DOS header
+        write(&mut file, 0, b"MZ");
+        write(&mut file, 0x3C, &(NT_HEADERS as u32).to_le_bytes());
+
+        // NT headers
+        write(&mut file, NT_HEADERS, b"PE\0\0");
+        write(&mut file, NT_HEADERS + 4, &0x8664u16.to_le_bytes());
+        write(
+            &mut file,
+            NT_HEADERS + 6,
+            &(self.sections.len() as u16).to_le_bytes(),
+        );
+        write(
+            &mut file,
+            NT_HEADERS + 20,
+            &(SIZE_OF_OPTIONAL_HEADER as u16).to_le_bytes(),
+        );
+        write(&mut file, NT_HEADERS + 22, &0x2022u16.to_le_bytes());
+
+        // Optional header
+        let opt = OPTIONAL_HEADER;
+        write(&mut file, opt, &0x20Bu16.to_le_bytes());
+        write(&mut file, opt + 24, &IMAGE_BASE.to_le_bytes());
+        write(&mut file, opt + 32, &self.section_alignment.to_le_bytes());
+        write(&mut file, opt + 36, &self.file_alignment.to_le_bytes());
+        write(&mut file, opt + 48, &6u16.to_le_bytes());
+        write(&mut file, opt + 56, &size_of_image.to_le_bytes());
+        write(&mut file, opt + 60, &SIZE_OF_HEADERS.to_le_bytes());
+        write(&mut file, opt + 68, &2u16.to_le_bytes());
+        write(&mut file, opt + 108, &16u32.to_le_bytes());
+
+        for (index, rva, size) in &self.directories {
+            write(&mut file, opt + 112 + index * 8, &rva.to_le_bytes());
+            write(&mut file, opt + 116 + index * 8, &size.to_le_bytes());
+        }
+
+        let mut raw_cursor = SIZE_OF_HEADERS;
+
+        for (idx, section) in self.sections.iter().enumerate() {
+            let size_of_raw_data = section
+                .size_of_raw_data
+                .unwrap_or_else(|| align_up(section.data.len() as u32, self.file_alignment));
+            let pointer_to_raw_data = match section.pointer_to_raw_data {
+                Some(pointer) => pointer,
+                None if size_of_raw_data == 0 => 0,
+                None => align_up(raw_cursor, self.file_alignment),
+            };
+            raw_cursor = raw_cursor.max(pointer_to_raw_data + size_of_raw_data);
+
+            let header = SECTION_HEADERS + idx * 40;
+            write(&mut file, header, section.name.as_bytes());
+            write(&mut file, header + 8, &section.virtual_size.to_le_bytes());
+            write(
+                &mut file,
+                header + 12,
+                &section.virtual_address.to_le_bytes(),
+            );
+            write(&mut file, header + 16, &size_of_raw_data.to_le_bytes());
+            write(&mut file, header + 20, &pointer_to_raw_data.to_le_bytes());
+            write(
+                &mut file,
+                header + 36,
+                &section.characteristics.to_le_bytes(),
+            );
+
+            if !section.data.is_empty() {
+                write(&mut file, pointer_to_raw_data as usize, &section.data);
+            }
+            if pointer_to_raw_data != 0 {
+                let raw_end = (pointer_to_raw_data + size_of_raw_data) as usize;
+                file.resize(file.len().max(raw_end), 0);
+            }
+        }
+
+        if let Some(size) = self.file_size {
+            file.resize(size, 0);
+        }
+
+        file
+    }
+
+    pub fn library(self) -> Library {
+        Library {
+            file_data: self.build(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Loads one of the DLLs in `service/fixtures`.
+pub fn fixture(name: &str) -> Library {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(name);
+
+    Library {
+        file_data: std::fs::read(&path)
+            .unwrap_or_else(|err| panic!("reading {}: {err}", path.display())),
+        ..Default::default()
+    }
+}